@@ -0,0 +1,58 @@
+/**
+ * thegrep - Pathological Pattern Benchmark
+ *
+ * Runs the release binary over single lines of n 'a's for doubling n
+ * with patterns that make a backtracking matcher take exponential time.
+ * The simulation is linear in the input, so the time per character
+ * should stay flat as n grows.
+ *
+ * Run with `cargo bench`.
+ */
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+const PATTERNS: &[&str] = &["(a*)*b", "(a|aa)*b", "(a?){20}a{20}b", ".*.*.*=.*"];
+const SIZES: &[usize] = &[25_000, 50_000, 100_000, 200_000];
+const RUNS: u32 = 3;
+
+fn main() {
+    println!(
+        "{:<18} {:>8} {:>12} {:>10}",
+        "pattern", "n", "time", "ns/char"
+    );
+    for pattern in PATTERNS {
+        for &n in SIZES {
+            let line = format!("{}\n", "a".repeat(n));
+            let best = (0..RUNS)
+                .map(|_| time_search(pattern, &line))
+                .min()
+                .unwrap();
+            println!(
+                "{:<18} {:>8} {:>10.2?} {:>10.1}",
+                pattern,
+                n,
+                best,
+                best.as_nanos() as f64 / n as f64
+            );
+        }
+    }
+}
+
+fn time_search(pattern: &str, input: &str) -> Duration {
+    let start = Instant::now();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_thegrep"))
+        .arg(pattern)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("failed to start thegrep");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait().expect("thegrep did not finish");
+    start.elapsed()
+}
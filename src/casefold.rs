@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Case Folding
+ *
+ * Case-insensitive patterns are compiled by widening each literal and
+ * class to every case variant of the chars it names, so the automata
+ * themselves never need to know about case.
+ */
+
+/**
+ * Widen some inclusive ranges of chars to include every char that is
+ * equal to one of them ignoring case. The result is sorted and merged.
+ */
+pub fn fold_ranges(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let table = orbits();
+    let mut chars: Vec<(char, char)> = ranges.to_vec();
+    for &(lo, hi) in ranges {
+        for (_, orbit) in table.range(lo..=hi) {
+            chars.extend(orbit.iter().map(|c| (*c, *c)));
+        }
+    }
+    merge(chars)
+}
+
+/**
+ * Sort ranges and merge those that overlap or touch.
+ */
+fn merge(mut ranges: Vec<(char, char)>) -> Vec<(char, char)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(char, char)> = vec![];
+    for (lo, hi) in ranges {
+        match merged.last_mut() {
+            Some(last) if lo as u32 <= last.1 as u32 + 1 => last.1 = last.1.max(hi),
+            _ => merged.push((lo, hi)),
+        }
+    }
+    merged
+}
+
+/**
+ * Every cased char mapped to the other chars of its case orbit, the set
+ * of chars that are all equal ignoring case, such as k, K and the Kelvin
+ * sign. The table is built from std's case mappings on first use.
+ */
+fn orbits() -> &'static BTreeMap<char, Vec<char>> {
+    static ORBITS: OnceLock<BTreeMap<char, Vec<char>>> = OnceLock::new();
+    ORBITS.get_or_init(|| {
+        // union-find over the chars linked by a one-char case mapping
+        let mut parent: HashMap<char, char> = HashMap::new();
+        // no char past the Supplementary Multilingual Plane has a case
+        for c in (0..0x20000).filter_map(std::char::from_u32) {
+            // dotless i uppercases to I but does not fold to it; linking
+            // them would make i and ı equal too
+            let upper = if c == '\u{131}' {
+                None
+            } else {
+                single(c.to_uppercase())
+            };
+            for other in single(c.to_lowercase()).into_iter().chain(upper) {
+                if other != c {
+                    let (a, b) = (root(&parent, c), root(&parent, other));
+                    parent.entry(a).or_insert(a);
+                    parent.entry(b).or_insert(b);
+                    if a != b {
+                        parent.insert(a, b);
+                    }
+                }
+            }
+        }
+        let mut groups: HashMap<char, Vec<char>> = HashMap::new();
+        let members: Vec<char> = parent.keys().cloned().collect();
+        for c in members {
+            let r = root(&parent, c);
+            groups.entry(r).or_default().push(c);
+        }
+        let mut table = BTreeMap::new();
+        for orbit in groups.values() {
+            for c in orbit {
+                let others = orbit.iter().cloned().filter(|other| other != c).collect();
+                table.insert(*c, others);
+            }
+        }
+        table
+    })
+}
+
+/**
+ * The representative of the orbit a char has been merged into so far.
+ */
+fn root(parent: &HashMap<char, char>, c: char) -> char {
+    let mut c = c;
+    while let Some(&p) = parent.get(&c) {
+        if p == c {
+            break;
+        }
+        c = p;
+    }
+    c
+}
+
+/**
+ * The char a case mapping produces, unless it produces several.
+ */
+fn single(mut mapping: impl Iterator<Item = char>) -> Option<char> {
+    match (mapping.next(), mapping.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    #[test]
+    fn ascii() {
+        assert_eq!(fold_ranges(&[('a', 'a')]), vec![('A', 'A'), ('a', 'a')]);
+        assert_eq!(fold_ranges(&[('1', '1')]), vec![('1', '1')]);
+        assert_eq!(
+            fold_ranges(&[('a', 'c'), ('X', 'X')]),
+            vec![('A', 'C'), ('X', 'X'), ('a', 'c'), ('x', 'x')]
+        );
+    }
+
+    #[test]
+    fn beyond_ascii() {
+        assert_eq!(fold_ranges(&[('é', 'é')]), vec![('É', 'É'), ('é', 'é')]);
+        // k, K and the Kelvin sign
+        assert_eq!(
+            fold_ranges(&[('k', 'k')]),
+            vec![('K', 'K'), ('k', 'k'), ('\u{212a}', '\u{212a}')]
+        );
+        // final and medial sigma fold together with capital sigma
+        assert_eq!(fold_ranges(&[('ς', 'ς')]), vec![('Σ', 'Σ'), ('ς', 'σ')]);
+    }
+
+    #[test]
+    fn dotless_i() {
+        assert_eq!(fold_ranges(&[('i', 'i')]), vec![('I', 'I'), ('i', 'i')]);
+        assert_eq!(fold_ranges(&[('ı', 'ı')]), vec![('ı', 'ı')]);
+    }
+
+    #[test]
+    fn merges_ranges() {
+        assert_eq!(
+            fold_ranges(&[('A', 'Z'), ('a', 'z')]),
+            vec![('A', 'Z'), ('a', 'z'), ('ſ', 'ſ'), ('\u{212a}', '\u{212a}')]
+        );
+    }
+}
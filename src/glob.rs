@@ -0,0 +1,234 @@
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Globs
+ *
+ * Shell-style wildcard patterns for choosing which files to search. A *
+ * matches any run of characters within one path component, ? matches
+ * any single one, and [...] matches one from a set. A ** that stands for
+ * a whole component matches any number of components, including none.
+ */
+
+/**
+ * The pieces a glob is compiled into.
+ */
+#[derive(Debug, Clone)]
+enum Piece {
+    Literal(char),
+    // ?, any one char other than /
+    AnyChar,
+    // *, any run of chars other than /
+    Star,
+    // **/, any run of whole components, each with its trailing /
+    AnyDirs,
+    // a trailing /**, everything beneath a directory
+    Beneath,
+    // a bracket expression: negated?, then inclusive ranges of chars
+    Class(bool, Vec<(char, char)>),
+}
+
+/**
+ * A compiled glob.
+ */
+#[derive(Debug, Clone)]
+pub struct Glob {
+    pieces: Vec<Piece>,
+}
+
+impl Glob {
+    /**
+     * Compile a glob, failing if a bracket expression is never closed or
+     * the pattern ends in a lone backslash.
+     */
+    pub fn new(pattern: &str) -> Result<Glob, String> {
+        let chars: Vec<char> = pattern.chars().collect();
+        let mut pieces = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' if chars.get(i + 1) == Some(&'*') => {
+                    let whole = i == 0 || chars[i - 1] == '/';
+                    match chars.get(i + 2) {
+                        Some('/') if whole => {
+                            pieces.push(Piece::AnyDirs);
+                            i += 3;
+                            continue;
+                        }
+                        None if whole && i > 0 => {
+                            // the / before has already been pushed
+                            pieces.push(Piece::Beneath);
+                        }
+                        None if whole => {
+                            pieces.push(Piece::AnyDirs);
+                            pieces.push(Piece::Star);
+                        }
+                        // ** inside a component is no different from *
+                        _ => pieces.push(Piece::Star),
+                    }
+                    i += 2;
+                    continue;
+                }
+                '*' => pieces.push(Piece::Star),
+                '?' => pieces.push(Piece::AnyChar),
+                '[' => {
+                    let (class, end) = class(&chars, i + 1)
+                        .ok_or_else(|| format!("Unclosed [ in glob {}", pattern))?;
+                    pieces.push(class);
+                    i = end;
+                    continue;
+                }
+                '\\' => match chars.get(i + 1) {
+                    Some(c) => {
+                        pieces.push(Piece::Literal(*c));
+                        i += 1;
+                    }
+                    None => return Err(format!("Trailing \\ in glob {}", pattern)),
+                },
+                c => pieces.push(Piece::Literal(c)),
+            }
+            i += 1;
+        }
+        Ok(Glob { pieces })
+    }
+
+    /**
+     * Whether the glob matches the whole of a path, whose components are
+     * separated by /.
+     */
+    pub fn matches(&self, path: &str) -> bool {
+        let text: Vec<char> = path.chars().collect();
+        // memo[p * (len + 1) + t] caches whether pieces[p..] match text[t..]
+        let mut memo = vec![None; (self.pieces.len() + 1) * (text.len() + 1)];
+        self.matches_at(&text, 0, 0, &mut memo)
+    }
+
+    fn matches_at(&self, text: &[char], p: usize, t: usize, memo: &mut [Option<bool>]) -> bool {
+        let key = p * (text.len() + 1) + t;
+        if let Some(known) = memo[key] {
+            return known;
+        }
+        let rest = &text[t..];
+        let result = match self.pieces.get(p) {
+            None => rest.is_empty(),
+            Some(Piece::Literal(c)) => {
+                rest.first() == Some(c) && self.matches_at(text, p + 1, t + 1, memo)
+            }
+            Some(Piece::AnyChar) => {
+                rest.first().is_some_and(|c| *c != '/') && self.matches_at(text, p + 1, t + 1, memo)
+            }
+            Some(Piece::Class(negated, ranges)) => {
+                rest.first().is_some_and(|c| {
+                    *c != '/' && ranges.iter().any(|(lo, hi)| lo <= c && c <= hi) != *negated
+                }) && self.matches_at(text, p + 1, t + 1, memo)
+            }
+            Some(Piece::Star) => {
+                let run = rest.iter().take_while(|c| **c != '/').count();
+                (0..=run).any(|n| self.matches_at(text, p + 1, t + n, memo))
+            }
+            Some(Piece::AnyDirs) => {
+                self.matches_at(text, p + 1, t, memo)
+                    || (0..rest.len())
+                        .filter(|n| rest[*n] == '/')
+                        .any(|n| self.matches_at(text, p + 1, t + n + 1, memo))
+            }
+            Some(Piece::Beneath) => !rest.is_empty(),
+        };
+        memo[key] = Some(result);
+        result
+    }
+}
+
+/**
+ * Compile the bracket expression whose body starts at chars[i], giving
+ * the class and the index just past its closing ]. A leading ! or ^
+ * negates it, and a ] first in the body is a member.
+ */
+fn class(chars: &[char], mut i: usize) -> Option<(Piece, usize)> {
+    let negated = matches!(chars.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut ranges = vec![];
+    let mut first = true;
+    loop {
+        let lo = match chars.get(i)? {
+            ']' if !first => return Some((Piece::Class(negated, ranges), i + 1)),
+            '\\' => {
+                i += 1;
+                *chars.get(i)?
+            }
+            c => *c,
+        };
+        first = false;
+        i += 1;
+        if chars.get(i) == Some(&'-') && chars.get(i + 1).is_some_and(|c| *c != ']') {
+            let hi = match chars[i + 1] {
+                '\\' => {
+                    i += 1;
+                    *chars.get(i + 1)?
+                }
+                c => c,
+            };
+            ranges.push((lo, hi));
+            i += 2;
+        } else {
+            ranges.push((lo, lo));
+        }
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern).unwrap().matches(path)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(matches("*.rs", ".rs"));
+        assert!(!matches("*.rs", "main.rc"));
+        assert!(!matches("*.rs", "src/main.rs"));
+        assert!(matches("?.c", "a.c"));
+        assert!(!matches("?.c", "ab.c"));
+        assert!(!matches("a?b", "a/b"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches("[abc].txt", "b.txt"));
+        assert!(!matches("[abc].txt", "d.txt"));
+        assert!(matches("[a-c]*", "cat"));
+        assert!(matches("[!a-c]*", "dog"));
+        assert!(!matches("[^a-c]*", "cat"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("[\\]]", "]"));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(matches("**/foo", "foo"));
+        assert!(matches("**/foo", "a/b/foo"));
+        assert!(!matches("**/foo", "a/xfoo"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(matches("a/**/b", "a/x/y/b"));
+        assert!(matches("a/**", "a/x/y"));
+        assert!(!matches("a/**", "a"));
+        assert!(matches("**", "a/b"));
+        assert!(matches("a**b", "axxb"));
+        assert!(!matches("a**b", "a/b"));
+    }
+
+    #[test]
+    fn bad_globs() {
+        assert!(Glob::new("[abc").is_err());
+        assert!(Glob::new("abc\\").is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::glob::Glob;
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Ignore Files
+ *
+ * Rules from .gitignore, .ignore and the global git excludes file, with
+ * git's semantics: a rule ending in / names only directories, one with a
+ * / anywhere else is anchored to the directory of its file, a leading !
+ * re-includes what an earlier rule excluded, and the last rule matching
+ * a path decides.
+ */
+
+/**
+ * One line of an ignore file.
+ */
+#[derive(Debug)]
+struct Rule {
+    glob: Glob,
+    negated: bool,
+    dir_only: bool,
+}
+
+/**
+ * The rules of one ignore file, which apply to paths beneath its base
+ * directory. A file read from above base matches paths as though from
+ * its own directory, with base at prefix beneath it.
+ */
+#[derive(Debug)]
+pub struct Ignore {
+    base: PathBuf,
+    prefix: PathBuf,
+    rules: Vec<Rule>,
+}
+
+impl Ignore {
+    /**
+     * Parse the text of an ignore file whose rules are relative to base.
+     * Blank lines, comments and rules that are not valid globs are
+     * skipped, as git does.
+     */
+    pub fn new(base: &Path, text: &str) -> Ignore {
+        Ignore {
+            base: base.to_path_buf(),
+            prefix: PathBuf::new(),
+            rules: text.lines().filter_map(rule).collect(),
+        }
+    }
+
+    /**
+     * Match paths as though base were at prefix beneath the directory
+     * the rules are relative to.
+     */
+    pub fn beneath(mut self, prefix: &Path) -> Ignore {
+        self.prefix = prefix.to_path_buf();
+        self
+    }
+
+    /**
+     * Read an ignore file, if there is one, applying it beneath base.
+     */
+    pub fn from_file(base: &Path, path: &Path) -> Option<Ignore> {
+        let text = fs::read_to_string(path).ok()?;
+        Some(Ignore::new(base, &text))
+    }
+
+    /**
+     * Whether the rules ignore a path: Some(true) if the last rule to
+     * match it excludes it, Some(false) if that rule re-includes it, and
+     * None if no rule matches.
+     */
+    pub fn matched(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let relative = self.prefix.join(path.strip_prefix(&self.base).ok()?);
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.glob.matches(&relative))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/**
+ * The global excludes file git reads by default, under the XDG config
+ * directory.
+ */
+pub fn global_excludes() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("git").join("ignore"))
+}
+
+/**
+ * Parse one line of an ignore file into a rule.
+ */
+fn rule(line: &str) -> Option<Rule> {
+    let line = trim_trailing_spaces(line);
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, line) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    let (dir_only, line) = match line.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, line),
+    };
+    // a rule with no / but the one ending it matches at any depth
+    let pattern = match line.strip_prefix('/') {
+        Some(rest) => rest.to_string(),
+        None if line.contains('/') => line.to_string(),
+        None => format!("**/{}", line),
+    };
+    let glob = Glob::new(&pattern).ok()?;
+    Some(Rule {
+        glob,
+        negated,
+        dir_only,
+    })
+}
+
+/**
+ * Trailing spaces are dropped unless escaped with a backslash.
+ */
+fn trim_trailing_spaces(line: &str) -> &str {
+    let mut end = line.len();
+    while line[..end].ends_with(' ') && !line[..end - 1].ends_with('\\') {
+        end -= 1;
+    }
+    &line[..end]
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    fn ignored(rules: &str, path: &str, is_dir: bool) -> Option<bool> {
+        let ignore = Ignore::new(Path::new("/repo"), rules);
+        ignore.matched(&Path::new("/repo").join(path), is_dir)
+    }
+
+    #[test]
+    fn unanchored() {
+        assert_eq!(ignored("*.o", "main.o", false), Some(true));
+        assert_eq!(ignored("*.o", "src/lib/main.o", false), Some(true));
+        assert_eq!(ignored("*.o", "main.c", false), None);
+        assert_eq!(ignored("build", "src/build", true), Some(true));
+    }
+
+    #[test]
+    fn anchored() {
+        assert_eq!(ignored("/build", "build", true), Some(true));
+        assert_eq!(ignored("/build", "src/build", true), None);
+        assert_eq!(ignored("doc/*.html", "doc/a.html", false), Some(true));
+        assert_eq!(ignored("doc/*.html", "src/doc/a.html", false), None);
+        assert_eq!(ignored("doc/**/*.png", "doc/a/b/c.png", false), Some(true));
+    }
+
+    #[test]
+    fn directories_only() {
+        assert_eq!(ignored("target/", "target", true), Some(true));
+        assert_eq!(ignored("target/", "target", false), None);
+        assert_eq!(ignored("target/", "a/target", true), Some(true));
+    }
+
+    #[test]
+    fn negation() {
+        let rules = "*.log\n!keep.log\n";
+        assert_eq!(ignored(rules, "a.log", false), Some(true));
+        assert_eq!(ignored(rules, "keep.log", false), Some(false));
+        // a later rule takes precedence again
+        assert_eq!(ignored("!keep.log\n*.log", "keep.log", false), Some(true));
+    }
+
+    #[test]
+    fn comments_and_escapes() {
+        assert_eq!(ignored("# comment", "# comment", false), None);
+        assert_eq!(ignored("\\#hash", "#hash", false), Some(true));
+        assert_eq!(ignored("\\!bang", "!bang", false), Some(true));
+        assert_eq!(ignored("trailing   ", "trailing", false), Some(true));
+        assert_eq!(ignored("space\\ ", "space ", false), Some(true));
+        assert_eq!(ignored("\n\n", "", false), None);
+    }
+
+    #[test]
+    fn outside_base() {
+        let ignore = Ignore::new(Path::new("/repo/sub"), "*");
+        assert_eq!(ignore.matched(Path::new("/repo/x"), false), None);
+    }
+
+    #[test]
+    fn beneath_prefix() {
+        // rules from /repo/.gitignore, for a walk of sub from /repo/sub
+        let ignore = Ignore::new(Path::new("."), "/sub/gen\n/gen\n").beneath(Path::new("sub"));
+        assert_eq!(ignore.matched(Path::new("./gen"), true), Some(true));
+        assert_eq!(ignore.matched(Path::new("./x/gen"), true), None);
+    }
+}
@@ -0,0 +1,229 @@
+/*
+ * thegrep - Tar Heel egrep - JSON Lines Output
+ *
+ * With --json every event of a search is written as one JSON object on a
+ * line of its own, so that tools need not parse the plain output, where
+ * a file name may itself hold a colon. Each object has a "type" naming
+ * the event, and the fields listed for it:
+ *
+ *   begin    path
+ *   match    path, line_number, byte_offset, text, submatches
+ *   context  path, line_number, byte_offset, text
+ *   end      path, stats: lines, matched_lines, matches, bytes
+ *   summary  stats: files, files_matched, lines, matched_lines,
+ *            matches, bytes
+ *
+ * A file's events come together, begin first and end last, with its
+ * match and context events in line order between them. The summary is
+ * the last line written. Path is null for standard input. Line numbers
+ * count from 1; byte_offset is that of the start of the line in its
+ * input. Text is the line without its terminator. Submatches are the
+ * non-empty matches in a line, each an object with the start and end
+ * byte offsets of the match within the line and its text; a line selected
+ * by -v has none. Matches counts submatches. Bytes counts the bytes
+ * searched, and lines the lines read, so neither counts a file past the
+ * point where reading it failed; such a file has no end event.
+ *
+ * JSON strings must be UTF-8, so bytes of a line that are not are written
+ * as U+FFFD in text. Offsets are still those of the bytes searched.
+ *
+ * Fields may be added to events in the future, but none will be removed
+ * or change meaning.
+ */
+
+/**
+ * Counts of what a search found, for one file or for all of them.
+ */
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub files: usize,
+    pub files_matched: usize,
+    pub lines: usize,
+    pub matched_lines: usize,
+    pub matches: usize,
+    pub bytes: usize,
+}
+
+impl Stats {
+    pub fn add(&mut self, other: &Stats) {
+        self.files += other.files;
+        self.files_matched += other.files_matched;
+        self.lines += other.lines;
+        self.matched_lines += other.matched_lines;
+        self.matches += other.matches;
+        self.bytes += other.bytes;
+    }
+}
+
+/**
+ * The begin event for a file, or for standard input if path is None.
+ */
+pub fn begin(path: Option<&str>) -> String {
+    format!("{{\"type\":\"begin\",\"path\":{}}}", path_value(path))
+}
+
+/**
+ * A match event for a selected line, with the byte spans of its
+ * submatches.
+ */
+pub fn matched(
+    path: Option<&str>,
+    number: usize,
+    offset: usize,
+    text: &[u8],
+    submatches: &[(usize, usize)],
+) -> String {
+    let submatches: Vec<String> = submatches
+        .iter()
+        .map(|(start, end)| {
+            format!(
+                "{{\"start\":{},\"end\":{},\"text\":{}}}",
+                start,
+                end,
+                string(&String::from_utf8_lossy(&text[*start..*end]))
+            )
+        })
+        .collect();
+    format!(
+        "{{\"type\":\"match\",{},\"submatches\":[{}]}}",
+        line_fields(path, number, offset, text),
+        submatches.join(",")
+    )
+}
+
+/**
+ * A context event for a line shown around a selected one.
+ */
+pub fn context(path: Option<&str>, number: usize, offset: usize, text: &[u8]) -> String {
+    format!(
+        "{{\"type\":\"context\",{}}}",
+        line_fields(path, number, offset, text)
+    )
+}
+
+/**
+ * The end event for a file, with what was found in it.
+ */
+pub fn end(path: Option<&str>, stats: &Stats) -> String {
+    format!(
+        "{{\"type\":\"end\",\"path\":{},\"stats\":{{\"lines\":{},\"matched_lines\":{},\
+         \"matches\":{},\"bytes\":{}}}}}",
+        path_value(path),
+        stats.lines,
+        stats.matched_lines,
+        stats.matches,
+        stats.bytes
+    )
+}
+
+/**
+ * The summary event, with what was found in every file.
+ */
+pub fn summary(stats: &Stats) -> String {
+    format!(
+        "{{\"type\":\"summary\",\"stats\":{{\"files\":{},\"files_matched\":{},\"lines\":{},\
+         \"matched_lines\":{},\"matches\":{},\"bytes\":{}}}}}",
+        stats.files,
+        stats.files_matched,
+        stats.lines,
+        stats.matched_lines,
+        stats.matches,
+        stats.bytes
+    )
+}
+
+fn line_fields(path: Option<&str>, number: usize, offset: usize, text: &[u8]) -> String {
+    format!(
+        "\"path\":{},\"line_number\":{},\"byte_offset\":{},\"text\":{}",
+        path_value(path),
+        number,
+        offset,
+        string(&String::from_utf8_lossy(text))
+    )
+}
+
+fn path_value(path: Option<&str>) -> String {
+    path.map_or_else(|| String::from("null"), string)
+}
+
+/**
+ * A JSON string literal. Quotes, backslashes and control chars are
+ * escaped; everything else is written as it is, in UTF-8.
+ */
+fn string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' || c == '\u{7f}' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(string("\t\u{1}é"), "\"\\t\\u0001é\"");
+        assert_eq!(path_value(None), "null");
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        assert_eq!(
+            matched(None, 1, 0, b"caf\xe9!", &[(3, 4)]),
+            "{\"type\":\"match\",\"path\":null,\"line_number\":1,\"byte_offset\":0,\
+             \"text\":\"caf\u{fffd}!\",\"submatches\":[{\"start\":3,\"end\":4,\"text\":\"\u{fffd}\"}]}"
+        );
+    }
+
+    #[test]
+    fn events() {
+        assert_eq!(
+            begin(Some("a:b.txt")),
+            "{\"type\":\"begin\",\"path\":\"a:b.txt\"}"
+        );
+        assert_eq!(
+            matched(Some("f"), 3, 10, b"an id", &[(3, 5)]),
+            "{\"type\":\"match\",\"path\":\"f\",\"line_number\":3,\"byte_offset\":10,\
+             \"text\":\"an id\",\"submatches\":[{\"start\":3,\"end\":5,\"text\":\"id\"}]}"
+        );
+        assert_eq!(
+            context(None, 1, 0, b""),
+            "{\"type\":\"context\",\"path\":null,\"line_number\":1,\"byte_offset\":0,\
+             \"text\":\"\"}"
+        );
+        let stats = Stats {
+            files: 1,
+            files_matched: 1,
+            lines: 4,
+            matched_lines: 2,
+            matches: 3,
+            bytes: 20,
+        };
+        assert_eq!(
+            end(Some("f"), &stats),
+            "{\"type\":\"end\",\"path\":\"f\",\"stats\":{\"lines\":4,\"matched_lines\":2,\
+             \"matches\":3,\"bytes\":20}}"
+        );
+        let mut total = Stats::default();
+        total.add(&stats);
+        total.add(&stats);
+        assert_eq!(
+            summary(&total),
+            "{\"type\":\"summary\",\"stats\":{\"files\":2,\"files_matched\":2,\"lines\":8,\
+             \"matched_lines\":4,\"matches\":6,\"bytes\":40}}"
+        );
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(unused)]
-/**
+/*
  * thegrep - Tar Heel egrep
  *
  * Author(s): Lily Lou,Taylor Montgomery
@@ -33,33 +33,505 @@ struct Options {
     #[structopt(short = "d", long = "dot")]
     dot: bool,
 
+    /// Show Minimized DFA
+    #[structopt(long = "minimize")]
+    minimize: bool,
+
+    /// Print the named groups of each matching line as name=value columns
+    #[structopt(long = "captures")]
+    captures: bool,
+
+    /// Ignore case distinctions in the pattern and input
+    #[structopt(short = "i", long = "ignore-case")]
+    ignore_case: bool,
+
+    /// Select non-matching lines
+    #[structopt(short = "v", long = "invert-match")]
+    invert: bool,
+
+    /// Match only whole words, with no word character just before or after
+    #[structopt(short = "w", long = "word-regexp")]
+    word_regexp: bool,
+
+    /// Match only whole lines
+    #[structopt(short = "x", long = "line-regexp")]
+    line_regexp: bool,
+
+    /// Print only a count of selected lines per input
+    #[structopt(short = "c", long = "count")]
+    count: bool,
+
+    /// Print nothing, exiting at the first selected line
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Treat binary files as text: search them when recursing and print their lines
+    #[structopt(short = "a", long = "text")]
+    text: bool,
+
+    /// Skip binary files, even those named or read from standard input
+    #[structopt(short = "I")]
+    skip_binary: bool,
+
+    /// Don't warn about files that can't be read
+    #[structopt(short = "s", long = "no-messages")]
+    no_messages: bool,
+
+    /// Prefix each line with its line number
+    #[structopt(short = "n", long = "line-number")]
+    line_number: bool,
+
+    /// Prefix each line with the byte offset of its start
+    #[structopt(short = "b", long = "byte-offset")]
+    byte_offset: bool,
+
+    /// Prefix each line with the column of its first match
+    #[structopt(long = "column")]
+    column: bool,
+
+    /// Prefix each line with its file name
+    #[structopt(short = "H", long = "with-filename")]
+    with_filename: bool,
+
+    /// Never prefix lines with a file name
+    #[structopt(short = "h", long = "no-filename")]
+    no_filename: bool,
+
+    /// Print every match as path:line:column:line, for editors
+    #[structopt(long = "vimgrep")]
+    vimgrep: bool,
+
+    /// Print each file, selected line and context line as a JSON object on
+    /// its own line, then a summary
+    #[structopt(long = "json")]
+    json: bool,
+
+    /// Print only the matched parts of selected lines, one per line
+    #[structopt(short = "o", long = "only-matching")]
+    only_matching: bool,
+
+    /// Highlight matches and prefixes: auto, always or never
+    #[structopt(long = "color", default_value = "auto", raw(value_name = "\"WHEN\""))]
+    color: Color,
+
+    /// Print NUM lines of context after each selected line
+    #[structopt(short = "A", long = "after-context", raw(value_name = "\"NUM\""))]
+    after_context: Option<usize>,
+
+    /// Print NUM lines of context before each selected line
+    #[structopt(short = "B", long = "before-context", raw(value_name = "\"NUM\""))]
+    before_context: Option<usize>,
+
+    /// Print NUM lines of context around each selected line
+    #[structopt(short = "C", long = "context", raw(value_name = "\"NUM\""))]
+    context: Option<usize>,
+
+    /// Search directories recursively, skipping symbolic links within them
+    #[structopt(short = "r", long = "recursive")]
+    recursive: bool,
+
+    /// Search directories recursively, following all symbolic links
+    #[structopt(short = "R", long = "dereference-recursive")]
+    dereference_recursive: bool,
+
+    /// Search only files whose names match GLOB
+    #[structopt(
+        long = "include",
+        parse(try_from_str = "Glob::new"),
+        raw(value_name = "\"GLOB\"", number_of_values = "1")
+    )]
+    include: Vec<Glob>,
+
+    /// Skip files whose names match GLOB
+    #[structopt(
+        long = "exclude",
+        parse(try_from_str = "Glob::new"),
+        raw(value_name = "\"GLOB\"", number_of_values = "1")
+    )]
+    exclude: Vec<Glob>,
+
+    /// Skip directories whose names match GLOB
+    #[structopt(
+        long = "exclude-dir",
+        parse(try_from_str = "Glob::new"),
+        raw(value_name = "\"GLOB\"", number_of_values = "1")
+    )]
+    exclude_dir: Vec<Glob>,
+
+    /// Descend at most NUM directories below each path searched
+    #[structopt(long = "max-depth", raw(value_name = "\"NUM\""))]
+    max_depth: Option<usize>,
+
+    /// Search hidden files and directories when recursing
+    #[structopt(long = "hidden")]
+    hidden: bool,
+
+    /// Don't respect .gitignore, .ignore or git's global excludes file
+    #[structopt(long = "no-ignore")]
+    no_ignore: bool,
+
+    /// Search NUM files at once, defaulting to the number of cores
+    #[structopt(short = "j", long = "threads", raw(value_name = "\"NUM\""))]
+    jobs: Option<usize>,
+
+    /// Print files in path order rather than as they finish: path or none
+    #[structopt(long = "sort", default_value = "none", raw(value_name = "\"BY\""))]
+    sort: Sort,
+
     ///Generate Acceptable Strings
     #[structopt(short = "g", long = "gen", default_value = "0")]
     outputs: u16,
 
-    ///Regular Expression Pattern
-    patterns: String,
+    ///Matching Engine: nfa, dfa or lazy
+    #[structopt(long = "engine", default_value = "nfa")]
+    engine: Engine,
+
+    ///Lazy DFA Cache Size in bytes, with an optional K, M or G suffix
+    #[structopt(
+        long = "cache-limit",
+        default_value = "2M",
+        parse(try_from_str = "parse_size")
+    )]
+    cache_limit: usize,
 
-    ///Input File
+    ///Regular Expression Pattern, which may be repeated
+    #[structopt(
+        short = "e",
+        long = "regexp",
+        raw(value_name = "\"PATTERN\"", number_of_values = "1")
+    )]
+    patterns: Vec<String>,
+
+    ///Read Patterns from FILE, one per line; a blank line matches every line
+    #[structopt(
+        short = "f",
+        long = "file",
+        raw(value_name = "\"FILE\"", number_of_values = "1")
+    )]
+    pattern_files: Vec<String>,
+
+    /// Prefix each selected line with the numbers of the patterns it matches
+    #[structopt(long = "show-patterns")]
+    show_patterns: bool,
+
+    ///The Pattern, unless -e or -f gives them, then Input Files
+    #[structopt(raw(value_name = "\"PATTERN] [FILE\""))]
     paths: Vec<String>,
 }
 
+pub mod casefold;
+pub mod glob;
+use self::glob::Glob;
+pub mod ignore;
+pub mod json;
+use self::json::Stats;
 pub mod tokenizer;
 use self::tokenizer::Tokenizer;
 pub mod parser;
 use self::parser::Parser;
 pub mod nfa;
-use self::nfa::helpers::nfa_dot;
-use self::nfa::NFA;
+use self::nfa::dfa::DFA;
+use self::nfa::helpers::{dfa_dot, nfa_dot};
+use self::nfa::lazy::{Cache, LazyDFA};
+use self::nfa::set::NFASet;
+use self::nfa::{Flags, Matches, NFA};
+pub mod walk;
+use self::walk::{Walk, WalkOptions};
+
+/**
+ * The automata lines can be matched with. The NFA simulation is linear
+ * in the pattern per character; the DFA is a table lookup per character
+ * but may take exponential time and space to build. The lazy DFA builds
+ * only the states the input reaches, within a bounded cache.
+ */
+#[derive(Debug)]
+enum Engine {
+    Nfa,
+    Dfa,
+    Lazy,
+}
+
+impl std::str::FromStr for Engine {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Engine, String> {
+        match name {
+            "nfa" => Ok(Engine::Nfa),
+            "dfa" => Ok(Engine::Dfa),
+            "lazy" => Ok(Engine::Lazy),
+            _ => Err(format!(
+                "unknown engine {}, expected nfa, dfa or lazy",
+                name
+            )),
+        }
+    }
+}
+
+/**
+ * When to color output with ANSI escapes. Auto colors only when stdout
+ * is a terminal, and is settled once at startup.
+ */
+#[derive(Debug, PartialEq)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for Color {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Color, String> {
+        match name {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => Err(format!(
+                "unknown color {}, expected auto, always or never",
+                name
+            )),
+        }
+    }
+}
+
+/**
+ * The order results from several files are printed in. Files are
+ * searched in parallel, so by default each is printed when it finishes.
+ */
+#[derive(Debug, PartialEq)]
+enum Sort {
+    None,
+    Path,
+}
+
+impl std::str::FromStr for Sort {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Sort, String> {
+        match name {
+            "none" => Ok(Sort::None),
+            "path" => Ok(Sort::Path),
+            _ => Err(format!("unknown sort {}, expected path or none", name)),
+        }
+    }
+}
+
+/**
+ * The SGR codes output is colored with, the same as egrep's defaults.
+ */
+const MATCH_COLOR: &str = "01;31";
+const PATH_COLOR: &str = "35";
+const NUMBER_COLOR: &str = "32";
+const SEPARATOR_COLOR: &str = "36";
+
+/**
+ * The name standard input is shown by in place of a path.
+ */
+const STDIN: &str = "(standard input)";
+
+/**
+ * Parse a size in bytes such as 512, 64K or 2M.
+ */
+fn parse_size(size: &str) -> Result<usize, String> {
+    let (digits, scale) = match size.char_indices().last() {
+        Some((i, 'K')) | Some((i, 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M')) | Some((i, 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G')) | Some((i, 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    digits
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(scale))
+        .ok_or_else(|| format!("invalid size {}", size))
+}
+
+/**
+ * A compiled pattern, ready to search lines with the chosen engine. The
+ * NFA is kept alongside whichever automaton decides matches, since only
+ * it can report where a match and its groups are. A matcher is shared by
+ * every thread searching; what each needs of its own is in a Searcher.
+ */
+struct Matcher {
+    nfa: NFA,
+    automaton: Automaton,
+    // every pattern tagged apart, for --show-patterns
+    set: Option<NFASet>,
+}
+
+enum Automaton {
+    Nfa,
+    Dfa(DFA),
+    Lazy(LazyDFA),
+}
+
+impl Matcher {
+    //the numbers of the patterns a line matches, counting from 1
+    fn which(&self, line: &[u8]) -> Vec<usize> {
+        match &self.set {
+            Some(set) => set.matches(line).into_iter().map(|i| i + 1).collect(),
+            None => vec![],
+        }
+    }
+}
+
+/**
+ * A thread's handle on a shared matcher, with its own lazy DFA cache.
+ */
+struct Searcher<'m> {
+    matcher: &'m Matcher,
+    cache: Option<Cache>,
+}
+
+impl<'m> Searcher<'m> {
+    fn accepts(&mut self, line: &[u8]) -> bool {
+        match (&self.matcher.automaton, &mut self.cache) {
+            (Automaton::Lazy(lazy), Some(cache)) => lazy.accepts(cache, line),
+            (Automaton::Dfa(dfa), _) => dfa.accepts(line),
+            _ => self.matcher.nfa.accepts(line),
+        }
+    }
+}
+
+impl Matcher {
+    fn new(options: &Options) -> Matcher {
+        let nfa = compile(options, true);
+        let automaton = match options.engine {
+            Engine::Nfa => Automaton::Nfa,
+            Engine::Dfa => match DFA::new(&nfa) {
+                Ok(dfa) => Automaton::Dfa(dfa),
+                Err(msg) => {
+                    eprintln!("thegrep: {}, falling back to the NFA", msg);
+                    Automaton::Nfa
+                }
+            },
+            // the NFA is still needed to find matches in selected lines
+            Engine::Lazy => Automaton::Lazy(LazyDFA::new(nfa.clone(), options.cache_limit)),
+        };
+        let set = if options.show_patterns {
+            Some(NFASet::from_nfa(nfa.clone(), options.patterns.len()))
+        } else {
+            None
+        };
+        Matcher {
+            nfa,
+            automaton,
+            set,
+        }
+    }
+
+    fn searcher(&self) -> Searcher<'_> {
+        let cache = match &self.automaton {
+            Automaton::Lazy(lazy) => Some(lazy.create_cache()),
+            _ => None,
+        };
+        Searcher {
+            matcher: self,
+            cache,
+        }
+    }
+
+    fn find_iter<'m, 'h>(&'m self, line: &'h [u8]) -> Matches<'m, 'h> {
+        self.nfa.find_iter(line)
+    }
+}
+
+/**
+ * How a search went, which decides the exit status like egrep's: 0 if a
+ * line was selected, 1 if none was, and 2 if there was an error, unless
+ * -q found a match all the same.
+ */
+#[derive(Default)]
+struct Outcome {
+    matched: bool,
+    failed: bool,
+    // what every file searched held, for the --json summary
+    stats: Stats,
+}
+
+impl Outcome {
+    fn add(&mut self, stats: &Stats) {
+        self.matched |= stats.matched_lines > 0;
+        self.stats.add(stats);
+    }
+
+    fn status(&self, options: &Options) -> i32 {
+        if self.failed && !(options.quiet && self.matched) {
+            2
+        } else if self.matched {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+//the flags given on the command line, for searching within lines if
+//unanchored
+fn flags(options: &Options, unanchored: bool) -> Flags {
+    Flags {
+        unanchored,
+        case_insensitive: options.ignore_case,
+        whole_words: options.word_regexp,
+        whole_line: options.line_regexp,
+    }
+}
+
+//compile the pattern with the flags given on the command line, exiting
+//with the parse error if it is malformed
+fn compile(options: &Options, unanchored: bool) -> NFA {
+    match NFA::with_patterns(&options.patterns, flags(options, unanchored)) {
+        Ok(nfa) => nfa,
+        Err(msg) => {
+            eprintln!("thegrep: {}", msg);
+            std::process::exit(2);
+        }
+    }
+}
 
 fn main() {
-    let options = Options::from_args();
+    let mut options = Options::from_args();
+    gather_patterns(&mut options);
+    if options.color == Color::Auto {
+        options.color = if io::stdout().is_terminal() {
+            Color::Always
+        } else {
+            Color::Never
+        };
+    }
     eval(&options);
-    let result = if options.paths.len() > 0 {
+    let outcome = if !options.paths.is_empty() || recursive(&options) {
         print_files(&options)
     } else {
         print_stdin(&options)
     };
+    if options.json && !options.quiet {
+        println!("{}", json::summary(&outcome.stats));
+    }
+    std::process::exit(outcome.status(&options));
+}
+
+//the patterns are those given by -e and read from -f files, or else the
+//first argument, which is otherwise the first input file
+fn gather_patterns(options: &mut Options) {
+    for path in options.pattern_files.clone() {
+        match std::fs::read_to_string(&path) {
+            // a blank line is an empty pattern, which matches every line
+            Ok(text) => options.patterns.extend(text.lines().map(String::from)),
+            Err(e) => {
+                eprintln!("thegrep: {}: {}", path, e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if options.patterns.is_empty() && options.pattern_files.is_empty() {
+        if options.paths.is_empty() {
+            eprintln!("thegrep: no pattern given; see --help");
+            std::process::exit(2);
+        }
+        let pattern = options.paths.remove(0);
+        options.patterns.push(pattern);
+    }
 }
 
 fn eval(options: &Options) {
@@ -75,6 +547,10 @@ fn eval(options: &Options) {
         eval_show_dot(options);
     }
 
+    if options.minimize {
+        eval_show_minimized(options);
+    }
+
     if options.outputs > 0 {
         eval_gen(options);
     }
@@ -83,71 +559,689 @@ fn eval(options: &Options) {
 //if the tokens option is true, it will display the
 //command line message in tokenized form
 fn eval_show_tokens(options: &Options) {
-    let mut tokens = Tokenizer::new(&options.patterns);
-    while let Some(token) = tokens.next() {
-        println!("{:?}", token);
+    for pattern in &options.patterns {
+        let mut tokens = Tokenizer::new(pattern);
+        for token in tokens {
+            println!("{:?}", token);
+        }
+        println!();
     }
-    print!("\n");
 }
 
 //if the parse option is true, it will display the
 //command line message in parse tree format
 fn eval_show_parse(options: &Options) {
-    match Parser::parse(Tokenizer::new(&options.patterns)) {
-        Ok(statement) => {
-            println!("{:?}", statement);
-            //parse method is returning a statement
+    for pattern in &options.patterns {
+        match Parser::parse(Tokenizer::new(pattern)) {
+            Ok(statement) => {
+                println!("{:?}", statement);
+                //parse method is returning a statement
+            }
+            Err(msg) => eprintln!("thegrep: {}", msg),
         }
-        Err(msg) => eprintln!("thegrep: {}", msg),
+        println!();
     }
-    print!("\n");
 }
 //if dot option is true, it will produce a dot
 //representation of thegrep
 fn eval_show_dot(options: &Options) {
-    let nfa = NFA::from(&options.patterns).unwrap();
+    let nfa = compile(options, false);
     println!("{}", nfa_dot(&nfa));
     std::process::exit(0);
 }
 
+//if minimize option is true, it will produce a dot
+//representation of the minimized DFA
+fn eval_show_minimized(options: &Options) {
+    let nfa = compile(options, false);
+    match DFA::new(&nfa) {
+        Ok(dfa) => println!("{}", dfa_dot(&dfa.minimize())),
+        Err(msg) => {
+            eprintln!("thegrep: {}", msg);
+            std::process::exit(2);
+        }
+    }
+    std::process::exit(0);
+}
+
 fn eval_gen(options: &Options) {
-    let nfa = NFA::from(&format!("{}", &options.patterns)).unwrap();
+    let nfa = compile(options, false);
 
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
     for i in 0..options.outputs {
-        let string = nfa.gen();
-        println!("{}", string);
+        let mut string = nfa.gen().unwrap_or_else(|msg| {
+            eprintln!("thegrep: {}", msg);
+            std::process::exit(2);
+        });
+        string.push(b'\n');
+        out.write_all(&string)
+            .unwrap_or_else(|e| exit_on_write_error(e));
     }
     std::process::exit(0);
 }
 
-fn print_stdin(options: &Options) -> io::Result<()> {
-    let nfa = NFA::from(&format!(".*{}.*", &options.patterns)).unwrap();
+fn print_stdin(options: &Options) -> Outcome {
+    let matcher = Matcher::new(options);
+    check_captures(options, &matcher);
     let stdin = io::stdin();
     let reader = stdin.lock();
-    print_lines(reader, &nfa)
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut searcher = matcher.searcher();
+    let path = STDIN;
+    let mut outcome = Outcome::default();
+    match print_lines(reader, &mut searcher, options, path, &mut out) {
+        Ok(stats) => outcome.add(&stats),
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => exit_on_write_error(e),
+        Err(e) => {
+            warn(options, &format!("{}: {}", path, e));
+            outcome.failed = true;
+        }
+    }
+    outcome
 }
 
+use std::collections::{BTreeMap, VecDeque};
 use std::fs::File;
 use std::io;
-use std::io::BufRead;
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+//search the files given, or found by walking, on a pool of threads that
+//share the matcher. Each file's output is buffered and written whole, so
+//files never interleave; with --sort path they are written in path order.
+//With one thread there is nothing to keep apart, so nothing is buffered
+fn print_files(options: &Options) -> Outcome {
+    let matcher = Matcher::new(options);
+    check_captures(options, &matcher);
+    let mut outcome = Outcome::default();
+    let mut paths = search_paths(options, &mut outcome);
+    if options.sort == Sort::Path {
+        paths.sort();
+    }
+    let jobs = options
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, paths.len().max(1));
+    if jobs == 1 {
+        print_in_turn(&matcher, options, &paths, &mut outcome);
+        return outcome;
+    }
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            let sender = sender.clone();
+            let (matcher, paths, next) = (&matcher, &paths, &next);
+            scope.spawn(move || {
+                let mut searcher = matcher.searcher();
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let path = match paths.get(index) {
+                        Some(path) => path,
+                        None => break,
+                    };
+                    let mut out = vec![];
+                    let result = print_file(&mut searcher, options, path, &mut out);
+                    // the receiver is gone once writing has failed
+                    if sender.send((index, out, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+        write_outputs(receiver, options, &paths, &mut outcome);
+    });
+    outcome
+}
+
+//search the files one after another, writing straight to stdout as
+//lines are found
+fn print_in_turn(matcher: &Matcher, options: &Options, paths: &[PathBuf], outcome: &mut Outcome) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut searcher = matcher.searcher();
+    for path in paths {
+        let result = print_file(&mut searcher, options, path, &mut out);
+        if options.quiet && result.as_ref().is_ok_and(|stats| stats.matched_lines > 0) {
+            std::process::exit(0);
+        }
+        match result {
+            Ok(stats) => outcome.add(&stats),
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => exit_on_write_error(e),
+            Err(e) => {
+                warn(options, &format!("{}: {}", path.display(), e));
+                outcome.failed = true;
+            }
+        }
+    }
+}
+
+//write each file's output as it arrives, or with --sort path hold it
+//back until those of every file before it have been written. A file
+//that failed part way has what it printed written before the warning
+fn write_outputs(
+    receiver: mpsc::Receiver<(usize, Vec<u8>, io::Result<Stats>)>,
+    options: &Options,
+    paths: &[PathBuf],
+    outcome: &mut Outcome,
+) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (index, output, result) in receiver {
+        if options.quiet && result.as_ref().is_ok_and(|stats| stats.matched_lines > 0) {
+            std::process::exit(0);
+        }
+        pending.insert(index, (output, result));
+        // without --sort each is written as soon as it arrives
+        if options.sort == Sort::None {
+            next = index;
+        }
+        while let Some((output, result)) = pending.remove(&next) {
+            out.write_all(&output)
+                .unwrap_or_else(|e| exit_on_write_error(e));
+            match result {
+                Ok(stats) => outcome.add(&stats),
+                Err(e) => {
+                    warn(options, &format!("{}: {}", paths[next].display(), e));
+                    outcome.failed = true;
+                }
+            }
+            next += 1;
+        }
+    }
+}
 
-fn print_files(options: &Options) -> io::Result<()> {
-    let nfa = NFA::from(&format!(".*{}.*", &options.patterns)).unwrap();
-    for path in options.paths.iter() {
-        let file = File::open(path)?;
-        let reader = io::BufReader::new(file);
-        print_lines(reader, &nfa)?;
+//report a file that could not be searched, unless -s
+fn warn(options: &Options, message: &str) {
+    if !options.no_messages {
+        eprintln!("thegrep: {}", message);
     }
+}
+
+//stdout has failed, so there is no point going on; a closed pipe, as when
+//piping into head, is not worth a message
+fn exit_on_write_error(error: io::Error) -> ! {
+    if error.kind() != io::ErrorKind::BrokenPipe {
+        eprintln!("thegrep: write error: {}", error);
+    }
+    std::process::exit(2);
+}
+
+//the files to search: those given, with -r or -R the files beneath the
+//directories given, or beneath the working directory when none are.
+//Walks skip hidden, ignored and binary files unless asked not to
+fn search_paths(options: &Options, outcome: &mut Outcome) -> Vec<PathBuf> {
+    let mut paths = vec![];
+    if !recursive(options) {
+        for path in options.paths.iter() {
+            if Path::new(path).is_dir() {
+                warn(options, &format!("{}: Is a directory", path));
+                outcome.failed = true;
+                continue;
+            }
+            paths.push(PathBuf::from(path));
+        }
+        return paths;
+    }
+    let walk_options = WalkOptions {
+        include: options.include.clone(),
+        exclude: options.exclude.clone(),
+        exclude_dir: options.exclude_dir.clone(),
+        max_depth: options.max_depth,
+        follow_links: options.dereference_recursive,
+        hidden: options.hidden,
+        ignore_files: !options.no_ignore,
+    };
+    let implicit = [String::from(".")];
+    let roots = if options.paths.is_empty() {
+        &implicit[..]
+    } else {
+        &options.paths[..]
+    };
+    for root in roots {
+        for found in Walk::new(Path::new(root), &walk_options) {
+            match found {
+                // files under the implicit . are named without it
+                Ok(path) if options.paths.is_empty() => {
+                    paths.push(path.strip_prefix(".").unwrap().to_path_buf())
+                }
+                Ok(path) => paths.push(path),
+                Err(msg) => {
+                    warn(options, &msg);
+                    outcome.failed = true;
+                }
+            }
+        }
+    }
+    paths
+}
+
+fn print_file(
+    searcher: &mut Searcher,
+    options: &Options,
+    path: &Path,
+    out: &mut impl Write,
+) -> io::Result<Stats> {
+    let file = File::open(path)?;
+    let reader = io::BufReader::new(file);
+    print_lines(reader, searcher, options, &path.to_string_lossy(), out)
+}
 
+fn recursive(options: &Options) -> bool {
+    options.recursive || options.dereference_recursive
+}
+
+//an input is taken to be binary if its first block holds a NUL byte,
+//unless -a searches them all as text
+fn binary<R: BufRead>(options: &Options, reader: &mut R) -> io::Result<bool> {
+    if options.text {
+        return Ok(false);
+    }
+    Ok(reader.fill_buf()?.contains(&0))
+}
+
+/**
+ * A line of an input and where it was found.
+ */
+struct Line<'a> {
+    number: usize,
+    offset: usize,
+    text: &'a [u8],
+}
+
+//print the lines selected by the pattern, which are those that match it
+//or, with -v, those that don't, giving what was found; -c prints the
+//count instead, -q stops quietly at the first and --json prints events.
+//Context lines are kept in a ring buffer until a selected line shows
+//whether they are needed, and groups of lines that are not adjacent are
+//separated by --. Binary files met while recursing are skipped, and with
+//-I every binary input is; for any other, only whether it matches is said
+fn print_lines<R: BufRead>(
+    mut reader: R,
+    searcher: &mut Searcher,
+    options: &Options,
+    path: &str,
+    out: &mut impl Write,
+) -> io::Result<Stats> {
+    let binary = binary(options, &mut reader)?;
+    if binary && (recursive(options) || options.skip_binary) {
+        return Ok(Stats::default());
+    }
+    let before = options.before_context.or(options.context).unwrap_or(0);
+    let after = options.after_context.or(options.context).unwrap_or(0);
+    // -B may be far larger than any input, so the ring grows as lines come
+    let mut ring: VecDeque<(usize, usize, Vec<u8>)> = VecDeque::new();
+    let mut after_left = 0;
+    let mut last_printed = None;
+
+    let mut stats = Stats {
+        files: 1,
+        ..Stats::default()
+    };
+    let mut buffer = vec![];
+    let mut number = 0;
+    let mut offset = 0;
+    if options.json && !options.quiet {
+        writeln!(out, "{}", json::begin(json_path(path)))?;
+    }
+    loop {
+        buffer.clear();
+        let read = reader.read_until(b'\n', &mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        number += 1;
+        let line = Line {
+            number,
+            offset,
+            text: trim_newline(&buffer),
+        };
+        offset += read;
+        stats.lines = number;
+        stats.bytes = offset;
+        let selected = searcher.accepts(line.text) != options.invert;
+        if selected {
+            stats.matched_lines += 1;
+            stats.files_matched = 1;
+        }
+        if options.quiet {
+            if selected {
+                return Ok(stats);
+            }
+            continue;
+        }
+        if options.count && !options.json {
+            continue;
+        }
+        if selected && binary && !options.json {
+            writeln!(out, "Binary file {} matches", path)?;
+            return Ok(stats);
+        }
+        if selected {
+            for (number, offset, text) in ring.drain(..) {
+                let line = Line {
+                    number,
+                    offset,
+                    text: &text,
+                };
+                print_separator(options, &mut last_printed, &line, out)?;
+                print_context(options, path, &line, out)?;
+            }
+            print_separator(options, &mut last_printed, &line, out)?;
+            if options.json {
+                stats.matches += print_json_match(searcher.matcher, options, path, &line, out)?;
+            } else {
+                print_line(searcher.matcher, options, path, &line, out)?;
+            }
+            after_left = after;
+        } else if after_left > 0 {
+            print_separator(options, &mut last_printed, &line, out)?;
+            print_context(options, path, &line, out)?;
+            after_left -= 1;
+        } else if before > 0 {
+            if ring.len() == before {
+                ring.pop_front();
+            }
+            ring.push_back((line.number, line.offset, line.text.to_vec()));
+        }
+    }
+    if options.json && !options.quiet {
+        writeln!(out, "{}", json::end(json_path(path), &stats))?;
+    } else if options.count && !options.quiet {
+        if show_path(options) {
+            write!(out, "{}", paint(options, PATH_COLOR, path))?;
+            write!(out, "{}", paint(options, SEPARATOR_COLOR, ":"))?;
+        }
+        writeln!(out, "{}", stats.matched_lines)?;
+    }
+    Ok(stats)
+}
+
+//a line without its terminator, which may be \n or \r\n
+fn trim_newline(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+//with context on, print -- before a line that does not follow the last
+//line printed
+fn print_separator(
+    options: &Options,
+    last_printed: &mut Option<usize>,
+    line: &Line,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let context = options
+        .after_context
+        .or(options.before_context)
+        .or(options.context);
+    if let Some(last) = *last_printed {
+        if context.is_some() && line.number > last + 1 && !options.json {
+            writeln!(out, "{}", paint(options, SEPARATOR_COLOR, "--"))?;
+        }
+    }
+    *last_printed = Some(line.number);
     Ok(())
 }
 
-fn print_lines<R: BufRead>(reader: R, nfa: &NFA) -> io::Result<()> {
-    for line_result in reader.lines() {
-        let line_result = line_result?;
-        if nfa.accepts(&line_result) {
-            println!("{}", &line_result);
+//print a selected line with the prefixes asked for; --vimgrep prints the
+//line once for each match in it and -o prints each match on its own
+//line instead. Inverted lines have no matches to show, so -o prints
+//nothing for them
+fn print_line(
+    matcher: &Matcher,
+    options: &Options,
+    path: &str,
+    line: &Line,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if options.only_matching && options.invert {
+        return Ok(());
+    }
+    let which = pattern_numbers(matcher, options, line.text);
+    if options.vimgrep && !options.invert || options.only_matching {
+        for found in matcher.find_iter(line.text) {
+            if options.only_matching && found.start == found.end {
+                continue;
+            }
+            let column = if options.vimgrep || options.column {
+                Some(found.char_start + 1)
+            } else {
+                None
+            };
+            // -b gives the offset of the part printed
+            let at = Line {
+                offset: if options.only_matching {
+                    line.offset + found.start
+                } else {
+                    line.offset
+                },
+                ..*line
+            };
+            let text = if options.only_matching {
+                paint_bytes(options, MATCH_COLOR, found.as_bytes(line.text))
+            } else {
+                highlight(matcher, options, line.text)
+            };
+            let prefix = prefix(options, path, &at, column, ':');
+            write!(out, "{}{}", prefix, which)?;
+            write_line(out, &text)?;
         }
+        return Ok(());
+    }
+    let column = if options.column {
+        let first = matcher.find_iter(line.text).next();
+        Some(first.map_or(1, |found| found.char_start + 1))
+    } else {
+        None
+    };
+    write!(out, "{}{}", prefix(options, path, line, column, ':'), which)?;
+    if options.captures && !options.invert {
+        print_captures(&matcher.nfa, line.text, out)
+    } else if options.invert {
+        write_line(out, line.text)
+    } else {
+        write_line(out, &highlight(matcher, options, line.text))
+    }
+}
+
+//write a line of input as it was read, bytes that are not UTF-8 and all,
+//followed by a newline
+fn write_line(out: &mut impl Write, text: &[u8]) -> io::Result<()> {
+    out.write_all(text)?;
+    out.write_all(b"\n")
+}
+
+//print a context line, whose prefixes end in - rather than :; with -o
+//only the separators between groups of lines are printed
+fn print_context(
+    options: &Options,
+    path: &str,
+    line: &Line,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    if options.json {
+        let text = json::context(json_path(path), line.number, line.offset, line.text);
+        return writeln!(out, "{}", text);
+    }
+    if options.only_matching {
+        return Ok(());
+    }
+    write!(out, "{}", prefix(options, path, line, None, '-'))?;
+    write_line(out, line.text)
+}
+
+//print the JSON event for a selected line, giving how many submatches
+//it has; a line selected by -v has none
+fn print_json_match(
+    matcher: &Matcher,
+    options: &Options,
+    path: &str,
+    line: &Line,
+    out: &mut impl Write,
+) -> io::Result<usize> {
+    let submatches: Vec<(usize, usize)> = if options.invert {
+        vec![]
+    } else {
+        matcher
+            .find_iter(line.text)
+            .filter(|found| found.start < found.end)
+            .map(|found| (found.start, found.end))
+            .collect()
+    };
+    let text = json::matched(
+        json_path(path),
+        line.number,
+        line.offset,
+        line.text,
+        &submatches,
+    );
+    writeln!(out, "{}", text)?;
+    Ok(submatches.len())
+}
+
+//standard input has no path in JSON, rather than the name it is shown
+//by otherwise
+fn json_path(path: &str) -> Option<&str> {
+    if path == STDIN {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+//the fields printed before a line, in egrep's order, each followed by
+//the separator: file name, line number, column, then byte offset
+fn prefix(
+    options: &Options,
+    path: &str,
+    line: &Line,
+    column: Option<usize>,
+    separator: char,
+) -> String {
+    let separator = paint(options, SEPARATOR_COLOR, &separator.to_string());
+    let mut prefix = String::new();
+    if show_path(options) {
+        prefix.push_str(&paint(options, PATH_COLOR, path));
+        prefix.push_str(&separator);
+    }
+    if options.line_number || options.vimgrep {
+        prefix.push_str(&paint(options, NUMBER_COLOR, &line.number.to_string()));
+        prefix.push_str(&separator);
+    }
+    if let Some(column) = column {
+        prefix.push_str(&paint(options, NUMBER_COLOR, &column.to_string()));
+        prefix.push_str(&separator);
+    }
+    if options.byte_offset {
+        prefix.push_str(&paint(options, NUMBER_COLOR, &line.offset.to_string()));
+        prefix.push_str(&separator);
+    }
+    prefix
+}
+
+//with --show-patterns, the numbers of the patterns a selected line
+//matches as one more prefix, which inverted lines don't have
+fn pattern_numbers(matcher: &Matcher, options: &Options, line: &[u8]) -> String {
+    if !options.show_patterns || options.invert {
+        return String::new();
+    }
+    let numbers: Vec<String> = matcher
+        .which(line)
+        .iter()
+        .map(|number| number.to_string())
+        .collect();
+    format!(
+        "{}{}",
+        paint(options, NUMBER_COLOR, &numbers.join(",")),
+        paint(options, SEPARATOR_COLOR, ":")
+    )
+}
+
+//a line with each of its non-empty matches colored, or the line as it is
+//when color is off
+fn highlight(matcher: &Matcher, options: &Options, line: &[u8]) -> Vec<u8> {
+    if options.color != Color::Always {
+        return line.to_vec();
+    }
+    let mut highlighted = vec![];
+    let mut last = 0;
+    for found in matcher.find_iter(line) {
+        if found.start == found.end {
+            continue;
+        }
+        highlighted.extend_from_slice(&line[last..found.start]);
+        highlighted.extend(paint_bytes(options, MATCH_COLOR, found.as_bytes(line)));
+        last = found.end;
+    }
+    highlighted.extend_from_slice(&line[last..]);
+    highlighted
+}
+
+//wrap text in the escapes for an SGR color when color is on; the
+//trailing erase-in-line keeps the background from bleeding on wrap
+fn paint(options: &Options, color: &str, text: &str) -> String {
+    if options.color == Color::Always {
+        format!("\x1b[{}m\x1b[K{}\x1b[m\x1b[K", color, text)
+    } else {
+        text.to_string()
+    }
+}
+
+//paint for text from the input, which need not be UTF-8
+fn paint_bytes(options: &Options, color: &str, text: &[u8]) -> Vec<u8> {
+    if options.color == Color::Always {
+        let mut painted = format!("\x1b[{}m\x1b[K", color).into_bytes();
+        painted.extend_from_slice(text);
+        painted.extend_from_slice(b"\x1b[m\x1b[K");
+        painted
+    } else {
+        text.to_vec()
+    }
+}
+
+//file names are shown by default when there are several files to tell
+//apart, and always for --vimgrep; -H and -h override the default
+fn show_path(options: &Options) -> bool {
+    !options.no_filename
+        && (options.with_filename
+            || options.vimgrep
+            || recursive(options)
+            || options.paths.len() > 1)
+}
+
+//the captures option needs a named group to print
+fn check_captures(options: &Options, matcher: &Matcher) {
+    if options.captures && matcher.nfa.group_names().next().is_none() {
+        eprintln!("thegrep: --captures needs a named group such as (?P<name>...)");
+        std::process::exit(2);
+    }
+}
+
+//print each named group of a matching line as name=value, separated by
+//tabs, leaving the value empty when the group did not take part
+fn print_captures(nfa: &NFA, line: &[u8], out: &mut impl Write) -> io::Result<()> {
+    if let Some(captures) = nfa.captures(line) {
+        let columns: Vec<Vec<u8>> = nfa
+            .group_names()
+            .map(|name| {
+                let value = captures
+                    .name(name)
+                    .map_or(&[][..], |group| group.as_bytes(line));
+                let mut column = format!("{}=", name).into_bytes();
+                column.extend_from_slice(value);
+                column
+            })
+            .collect();
+        write_line(out, &columns.join(&b'\t'))?;
     }
     Ok(())
 }
@@ -2,9 +2,17 @@
 #![allow(non_snake_case)]
 use std::ops::Add;
 
+pub mod dfa;
 pub mod helpers;
+pub mod lazy;
+mod pike;
+pub mod set;
+mod sparse;
+mod utf8;
 
 // Starter code for PS06 - thegrep
+use self::sparse::SparseSet;
+use self::utf8::{Unit, Units};
 use self::State::*;
 /**
 * thegrep - Tar Heel Extended Regular Expressions - NFA
@@ -20,10 +28,11 @@ use super::parser::Parser;
 use super::parser::AST;
 use super::tokenizer::Tokenizer;
 use rand::distributions::Alphanumeric;
+use rand::seq::SliceRandom;
 use rand::*;
 use rand::{thread_rng, Rng};
 
-/**
+/*
  * ===== Public API =====
  */
 
@@ -31,83 +40,269 @@ use rand::{thread_rng, Rng};
  * An NFA is represented by an arena Vec of States
  * and a start state.
  */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct NFA {
     start: StateId,
+    // where the pattern itself begins, past any unanchored search loop
+    body: StateId,
+    // the number of capture groups, not counting the whole match
+    groups: usize,
+    // the named groups and their indices, in the order they appear
+    names: Vec<(String, usize)>,
     states: Vec<State>,
 }
 
+/**
+ * Where a pattern matched in a haystack, as byte offsets for slicing and
+ * char offsets for reporting columns, where a byte that is not part of
+ * a char counts as one. The end offsets are exclusive.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub start: usize,
+    pub end: usize,
+    pub char_start: usize,
+    pub char_end: usize,
+}
+
+impl Match {
+    /**
+     * The matched text of the haystack the match was found in.
+     */
+    pub fn as_str<'h>(&self, haystack: &'h str) -> &'h str {
+        &haystack[self.start..self.end]
+    }
+
+    /**
+     * The matched bytes of the haystack the match was found in.
+     */
+    pub fn as_bytes<'h>(&self, haystack: &'h [u8]) -> &'h [u8] {
+        &haystack[self.start..self.end]
+    }
+}
+
+/**
+ * The spans of the groups of a match. Group 0 is the whole match and the
+ * rest are numbered by their left parens; a group that did not take part
+ * in the match has no span.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Captures<'n> {
+    groups: Vec<Option<Match>>,
+    names: &'n [(String, usize)],
+}
+
+impl<'n> Captures<'n> {
+    /**
+     * The span of a group, if it took part in the match.
+     */
+    pub fn get(&self, group: usize) -> Option<Match> {
+        self.groups.get(group).cloned().flatten()
+    }
+
+    /**
+     * The spans of every group in order, starting with the whole match.
+     */
+    pub fn iter(&self) -> std::slice::Iter<'_, Option<Match>> {
+        self.groups.iter()
+    }
+
+    /**
+     * The span of a named group, if it took part in the match.
+     */
+    pub fn name(&self, name: &str) -> Option<Match> {
+        self.names
+            .iter()
+            .filter(|(named, _)| named == name)
+            .find_map(|(_, group)| self.get(*group))
+    }
+}
+
+/**
+ * An iterator over the non-overlapping matches in a haystack, from left
+ * to right. Created by `NFA::find_iter`.
+ */
+#[derive(Debug)]
+pub struct Matches<'n, 'h> {
+    nfa: &'n NFA,
+    haystack: &'h [u8],
+    // where the next search begins, in bytes and chars
+    start: usize,
+    char_start: usize,
+    // the end of the last match, after which an empty match is not reported
+    last_end: Option<usize>,
+}
+
+impl<'n, 'h> Iterator for Matches<'n, 'h> {
+    type Item = Match;
+
+    fn next(&mut self) -> Option<Match> {
+        while self.start <= self.haystack.len() {
+            let found = self
+                .nfa
+                .find_at(self.haystack, self.start, self.char_start)?;
+            if found.start == found.end {
+                // step over an empty match so the search makes progress
+                self.start = match utf8::decode(&self.haystack[found.end..]) {
+                    Some(unit) => found.end + unit.len(),
+                    None => found.end + 1,
+                };
+                self.char_start = found.char_end + 1;
+                if self.last_end == Some(found.end) {
+                    continue;
+                }
+            } else {
+                self.start = found.end;
+                self.char_start = found.char_end;
+            }
+            self.last_end = Some(found.end);
+            return Some(found);
+        }
+        None
+    }
+}
+
+/**
+ * Options for compiling a pattern, as set by command line flags.
+ * - unanchored wraps the pattern the way `NFA::unanchored` does
+ * - case_insensitive compiles it as if it began with (?i)
+ * - whole_words matches it only where no word char is just before or
+ *   after it, like egrep -w
+ * - whole_line matches it only against the whole input, like egrep -x,
+ *   and takes precedence over the other two
+ */
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Flags {
+    pub unanchored: bool,
+    pub case_insensitive: bool,
+    pub whole_words: bool,
+    pub whole_line: bool,
+}
+
 impl NFA {
     /**
      * Construct an NFA from a regular expression pattern.
      */
     pub fn from(regular_expression: &str) -> Result<NFA, String> {
-        let mut nfa = NFA::new();
+        NFA::with_flags(regular_expression, Flags::default())
+    }
 
-        let start = nfa.add_state(Start(None));
-        nfa.start = start;
+    /**
+     * Construct an NFA that finds the pattern anywhere in its input, the
+     * way egrep searches a line. Only a leading ^ pins the match to the
+     * start, and since `accepts` only needs a prefix of the input to
+     * match, only a trailing $ pins it to the end.
+     */
+    pub fn unanchored(regular_expression: &str) -> Result<NFA, String> {
+        let flags = Flags {
+            unanchored: true,
+            ..Flags::default()
+        };
+        NFA::with_flags(regular_expression, flags)
+    }
 
-        // Parse the Abstract Syntax Tree of the Regular Expression
-        let ast = &Parser::parse(Tokenizer::new(regular_expression))?;
-        // The "body" of the NFA is made of the states between Start and End
-        let body = nfa.gen_fragment(ast);
-        nfa.join(nfa.start, body.start);
+    /**
+     * Construct an NFA from a regular expression pattern with options.
+     */
+    pub fn with_flags(regular_expression: &str, flags: Flags) -> Result<NFA, String> {
+        NFA::build(&[regular_expression], flags)
+    }
 
-        let end = nfa.add_state(End);
-        nfa.join_fragment(&body, end);
+    /**
+     * Construct one NFA that matches wherever any of several patterns
+     * does, as if they were alternatives of one pattern. Their groups are
+     * numbered one after another, and a group name may be used by more
+     * than one pattern. No patterns at all match nothing.
+     */
+    pub fn with_patterns<S: AsRef<str>>(patterns: &[S], flags: Flags) -> Result<NFA, String> {
+        NFA::build(patterns, flags)
+    }
 
-        Ok(nfa)
+    /**
+     * Given an input string, simulate the NFA to determine if some
+     * prefix of the input is accepted, so a pattern built with
+     * `unanchored` is found anywhere in the input, like egrep. Input
+     * may be any bytes; those that are not valid UTF-8 are matched one
+     * at a time.
+     */
+    pub fn accepts<I: AsRef<[u8]> + ?Sized>(&self, input: &I) -> bool {
+        self.simulate(input.as_ref(), false)
     }
 
     /**
      * Given an input string, simulate the NFA to determine if the
-     * input is accepted by the input string.
-     */
-    pub fn accepts(&self, input: &str) -> bool {
-        let mut current = Vec::new(); // can be in multiple states at once
-        let mut next_states = Vec::new(); // vec of ids of next states
-        current.push(self.start);
-        let mut input_string = input.chars(); // iterator for input characters
-        while let Some(ch) = input_string.next() {
-            // iterate through string and for each char, go through a list of states
-            // and see if they match, send matches to next_states vector to parse through later
-            &self.parse_state(&mut current, &mut next_states);
-            while !current.is_empty() {
-                match &self.states[current.remove(0)] {
-                    State::Start(id) => {
-                        let mut parse_start = Vec::new();
-                        parse_start.push(id.unwrap());
-                        self.parse_state(&mut current, &mut parse_start);
-                    }
-                    State::Match(match_char, id) => match match_char {
-                        Char::Literal(match_character) => {
-                            if match_character == &ch {
-                                next_states.push(id.unwrap());
-                            }
-                        }
-                        Char::Any => {
-                            next_states.push(id.unwrap());
-                        }
-                    },
-                    State::End => return true, // for parsing (string).*
-                    _ => {}                    // should not hit
-                }
-            }
-        }
-        // parse through once more to see if there is an end state that wasn't reached
-        // ex: "ab" for nfa::from("ab.*")
-        &self.parse_state(&mut current, &mut next_states);
-        while !current.is_empty() {
-            match &self.states[current.remove(0)] {
-                State::End => return true,
-                _ => {}
-            }
+     * whole input is accepted.
+     */
+    pub fn accepts_full<I: AsRef<[u8]> + ?Sized>(&self, input: &I) -> bool {
+        self.simulate(input.as_ref(), true)
+    }
+
+    /**
+     * Find the leftmost-longest match of the pattern in a haystack: of
+     * the matches that start earliest, the one that ends latest, as POSIX
+     * specifies. Any search loop from `unanchored` is skipped, so this
+     * finds the same matches for NFAs built either way.
+     */
+    pub fn find<H: AsRef<[u8]> + ?Sized>(&self, haystack: &H) -> Option<Match> {
+        self.find_at(haystack.as_ref(), 0, 0)
+    }
+
+    /**
+     * Find the leftmost-longest match like `find`, along with the span
+     * of each parenthesized group within it. A group inside a loop
+     * reports its last iteration.
+     */
+    pub fn captures<H: AsRef<[u8]> + ?Sized>(&self, haystack: &H) -> Option<Captures<'_>> {
+        let groups = self.search(haystack.as_ref(), 0, 0, self.groups + 1)?;
+        Some(Captures {
+            groups,
+            names: &self.names,
+        })
+    }
+
+    /**
+     * The number of parenthesized groups in the pattern.
+     */
+    pub fn groups(&self) -> usize {
+        self.groups
+    }
+
+    /**
+     * The names of the named groups, in the order they first appear.
+     * Separate patterns may reuse a name, but each is given only once.
+     */
+    pub fn group_names(&self) -> impl Iterator<Item = &str> {
+        let names = &self.names;
+        names
+            .iter()
+            .enumerate()
+            .filter(move |(i, (name, _))| names[..*i].iter().all(|(seen, _)| seen != name))
+            .map(|(_, (name, _))| name.as_str())
+    }
+
+    /**
+     * Iterate over the successive non-overlapping leftmost-longest
+     * matches in a haystack. An empty match right after another match
+     * is skipped.
+     */
+    pub fn find_iter<'n, 'h, H: AsRef<[u8]> + ?Sized>(
+        &'n self,
+        haystack: &'h H,
+    ) -> Matches<'n, 'h> {
+        Matches {
+            nfa: self,
+            haystack: haystack.as_ref(),
+            start: 0,
+            char_start: 0,
+            last_end: None,
         }
-        false
     }
-    /**Given an NFA, generate strings accepted by the pattern
+
+    /**Given an NFA, generate strings accepted by the pattern, as bytes
+     * since a pattern may call for some that are not valid UTF-8. Fails
+     * if the path taken meets a class that accepts nothing.
      */
-    pub fn gen(&self) -> String {
+    pub fn gen(&self) -> Result<Vec<u8>, String> {
         //vector for randome or literal characters
         let mut gen_str = Vec::new();
 
@@ -125,18 +320,15 @@ impl NFA {
             match &self.states[next_states[0]] {
                 State::Start(id) => next_states.push(id.unwrap()),
                 State::Match(m_char, id) => {
-                    //add char to vector, if anychar generate and add random char
+                    //add char to vector, if anychar or class generate and add random char
                     next_states.push(id.unwrap());
-                    match m_char {
-                        Char::Literal(match_character) => gen_str.push(*match_character),
-                        Char::Any => {
-                            let mut rng = thread_rng();
-                            let mut c = rng.sample(Alphanumeric);
-                            gen_str.push(c);
-                        }
-                    }
+                    m_char.sample()?.encode(&mut gen_str);
                 }
 
+                //assertions and saves consume nothing, so just move past them
+                State::Assert(_, id) => next_states.push(id.unwrap()),
+                State::Save(_, id) => next_states.push(id.unwrap()),
+
                 State::Split(id1, id2) => {
                     //random bool will determine which split path to follow
                     let mut path = rand::random::<bool>();
@@ -147,7 +339,7 @@ impl NFA {
                     }
                 }
 
-                State::End => {
+                State::End(_) => {
                     break;
                 }
             }
@@ -156,15 +348,12 @@ impl NFA {
             next_states.remove(0);
         }
 
-        //collect gen_str vector to create string
-        let mut complete_str: String = String::new();
-        complete_str = gen_str.into_iter().collect();
-        complete_str
+        Ok(gen_str)
     }
 }
 
 #[cfg(test)]
-mod nfa {
+mod public_api {
     use super::*;
     mod nfa_accepts {
         use super::*;
@@ -172,153 +361,381 @@ mod nfa {
         #[test]
         fn accept_simple_true() {
             let nfa = NFA::from("b").unwrap();
-            assert_eq!(true, nfa.accepts("b"));
+            assert!(nfa.accepts("b"));
 
             let nfa = NFA::from("a").unwrap();
-            assert_eq!(true, nfa.accepts("a"));
+            assert!(nfa.accepts("a"));
 
             let nfa = NFA::from("us").unwrap();
-            assert_eq!(true, nfa.accepts("us"));
+            assert!(nfa.accepts("us"));
         }
 
         #[test]
         fn best_match() {
             let nfa = NFA::from(".*unc.*").unwrap();
-            assert_eq!(true, nfa.accepts("unc"));
-            assert_eq!(true, nfa.accepts("hellounc"));
-            assert_eq!(true, nfa.accepts("bounce"));
-            assert_eq!(true, nfa.accepts("uncork"));
-            assert_eq!(true, nfa.accepts("lunch"));
-            assert_eq!(true, nfa.accepts("munch"));
+            assert!(nfa.accepts("unc"));
+            assert!(nfa.accepts("hellounc"));
+            assert!(nfa.accepts("bounce"));
+            assert!(nfa.accepts("uncork"));
+            assert!(nfa.accepts("lunch"));
+            assert!(nfa.accepts("munch"));
         }
 
         #[test]
         fn Nomatch_anyKleeneStar() {
             let nfa = NFA::from(".*a.*").unwrap();
-            assert_eq!(false, nfa.accepts("cdc"));
-            assert_eq!(false, nfa.accepts("dgf"));
-            assert_eq!(false, nfa.accepts("bub"));
-            assert_eq!(false, nfa.accepts("hik"));
+            assert!(!nfa.accepts("cdc"));
+            assert!(!nfa.accepts("dgf"));
+            assert!(!nfa.accepts("bub"));
+            assert!(!nfa.accepts("hik"));
         }
 
         #[test]
         fn accept_kleenestar() {
             let nfa = NFA::from(".*ab*.*").unwrap();
-            assert_eq!(true, nfa.accepts("abb"));
-            assert_eq!(true, nfa.accepts("abbbbbbbbbbbb"));
-            assert_eq!(true, nfa.accepts("cab"));
+            assert!(nfa.accepts("abb"));
+            assert!(nfa.accepts("abbbbbbbbbbbb"));
+            assert!(nfa.accepts("cab"));
 
             let nfa = NFA::from(".*abd*").unwrap();
-            assert_eq!(true, nfa.accepts("abdd"));
-            assert_eq!(true, nfa.accepts("abb"));
+            assert!(nfa.accepts("abdd"));
+            assert!(nfa.accepts("abb"));
         }
 
         #[test]
         fn accepts_simple_false() {
             let nfa = NFA::from("abd").unwrap();
-            assert_eq!(false, nfa.accepts("c"));
+            assert!(!nfa.accepts("c"));
             let nfa = NFA::from(".*ab.*").unwrap();
-            assert_eq!(false, nfa.accepts("hat"));
+            assert!(!nfa.accepts("hat"));
         }
 
         #[test]
         fn simple_alteration_kleene() {
             let nfa = NFA::from(".*(x|y)").unwrap();
-            assert_eq!(true, nfa.accepts("x"));
-            assert_eq!(true, nfa.accepts("y"));
-            assert_eq!(true, nfa.accepts("ax"));
-            assert_eq!(true, nfa.accepts("ay"));
+            assert!(nfa.accepts("x"));
+            assert!(nfa.accepts("y"));
+            assert!(nfa.accepts("ax"));
+            assert!(nfa.accepts("ay"));
         }
 
         #[test]
         fn accept_alteration_anychar() {
             let nfa = NFA::from("(a|b).d").unwrap();
-            assert_eq!(true, nfa.accepts("bad"));
-            assert_eq!(true, nfa.accepts("bud"));
-            assert_eq!(false, nfa.accepts("bat"));
-            assert_eq!(true, nfa.accepts("and"));
+            assert!(nfa.accepts("bad"));
+            assert!(nfa.accepts("bud"));
+            assert!(!nfa.accepts("bat"));
+            assert!(nfa.accepts("and"));
         }
 
         #[test]
         fn accept_anychar() {
             let nfa = NFA::from(".....").unwrap();
-            assert_eq!(true, nfa.accepts("yikes"));
-            assert_eq!(true, nfa.accepts("zoned"));
-            assert_eq!(false, nfa.accepts("yay"));
-            assert_eq!(true, nfa.accepts("alimony"));
-            assert_eq!(false, nfa.accepts("oreo"));
+            assert!(nfa.accepts("yikes"));
+            assert!(nfa.accepts("zoned"));
+            assert!(!nfa.accepts("yay"));
+            assert!(nfa.accepts("alimony"));
+            assert!(!nfa.accepts("oreo"));
 
             let nfa = NFA::from("...*").unwrap();
-            assert_eq!(true, nfa.accepts("ah"));
+            assert!(nfa.accepts("ah"));
         }
 
         #[test]
         fn accept_caten() {
             let nfa = NFA::from(".*us.*").unwrap();
-            assert_eq!(true, nfa.accepts("transfuse"));
-            assert_eq!(true, nfa.accepts("suspicion"));
-            assert_eq!(true, nfa.accepts("use"));
-            assert_eq!(false, nfa.accepts("super"));
-            assert_eq!(false, nfa.accepts("happy"));
+            assert!(nfa.accepts("transfuse"));
+            assert!(nfa.accepts("suspicion"));
+            assert!(nfa.accepts("use"));
+            assert!(!nfa.accepts("super"));
+            assert!(!nfa.accepts("happy"));
         }
 
         #[test]
         fn caten_Kleenstar_alter_lvl1() {
             let nfa = NFA::from(".*(a|b)*...g.*").unwrap();
-            assert_eq!(true, nfa.accepts("ring"));
-            assert_eq!(true, nfa.accepts("programmer"));
-            assert_eq!(true, nfa.accepts("mythology"));
-            assert_eq!(true, nfa.accepts("applegate"));
-            assert_eq!(false, nfa.accepts("apple"));
+            assert!(nfa.accepts("ring"));
+            assert!(nfa.accepts("programmer"));
+            assert!(nfa.accepts("mythology"));
+            assert!(nfa.accepts("applegate"));
+            assert!(!nfa.accepts("apple"));
         }
 
         #[test]
         fn nfa_gone_wild() {
             let nfa = NFA::from("(.*a.*.((aa)*b|(e|d)))|(x*h(i|o))").unwrap();
-            assert_eq!(true, nfa.accepts("contemplate"));
-            assert_eq!(true, nfa.accepts("convalescences"));
-            assert_eq!(true, nfa.accepts("emulate"));
-            assert_eq!(true, nfa.accepts("emphasize"));
-            assert_eq!(false, nfa.accepts("goat"));
-            assert_eq!(false, nfa.accepts("glass"));
-            assert_eq!(false, nfa.accepts("easy"));
-            assert_eq!(false, nfa.accepts("decimal"));
+            assert!(nfa.accepts("contemplate"));
+            assert!(nfa.accepts("convalescences"));
+            assert!(nfa.accepts("emulate"));
+            assert!(nfa.accepts("emphasize"));
+            assert!(!nfa.accepts("goat"));
+            assert!(!nfa.accepts("glass"));
+            assert!(!nfa.accepts("easy"));
+            assert!(!nfa.accepts("decimal"));
         }
 
         #[test]
         fn alternation_catenation() {
             let nfa = NFA::from(".*(t|k)(a|i)(b|d).*").unwrap();
-            assert_eq!(true, nfa.accepts("tide"));
-            assert_eq!(true, nfa.accepts("table"));
-            assert_eq!(true, nfa.accepts("kid"));
-            assert_eq!(true, nfa.accepts("tab"));
-            assert_eq!(false, nfa.accepts("fast"));
-            assert_eq!(false, nfa.accepts("act"));
-            assert_eq!(false, nfa.accepts("the"));
+            assert!(nfa.accepts("tide"));
+            assert!(nfa.accepts("table"));
+            assert!(nfa.accepts("kid"));
+            assert!(nfa.accepts("tab"));
+            assert!(!nfa.accepts("fast"));
+            assert!(!nfa.accepts("act"));
+            assert!(!nfa.accepts("the"));
         }
 
         #[test]
         fn alt_in_an_alt() {
             let nfa = NFA::from("(a|b)|(t|z)").unwrap();
-            assert_eq!(true, nfa.accepts("bash"));
-            assert_eq!(true, nfa.accepts("avocado"));
-            assert_eq!(true, nfa.accepts("zebra"));
-            assert_eq!(false, nfa.accepts("hello"));
-            assert_eq!(false, nfa.accepts("unc"));
-            assert_eq!(false, nfa.accepts("got"));
+            assert!(nfa.accepts("bash"));
+            assert!(nfa.accepts("avocado"));
+            assert!(nfa.accepts("zebra"));
+            assert!(!nfa.accepts("hello"));
+            assert!(!nfa.accepts("unc"));
+            assert!(!nfa.accepts("got"));
         }
 
         #[test]
         fn automata() {
             let nfa = NFA::from("aut....a").unwrap();
-            assert_eq!(true, nfa.accepts("automata"));
+            assert!(nfa.accepts("automata"));
         }
 
         #[test]
         fn kleeneplus() {
             let nfa = NFA::from("hel+o").unwrap();
-            assert_eq!(true, nfa.accepts("helllllllllo"));
-            assert_eq!(false, nfa.accepts("heo"));
+            assert!(nfa.accepts("helllllllllo"));
+            assert!(!nfa.accepts("heo"));
+        }
+
+        #[test]
+        fn escaped_metacharacters() {
+            let nfa = NFA::from(".*10\\.0\\.0\\.1").unwrap();
+            assert!(nfa.accepts("ip 10.0.0.1"));
+            assert!(!nfa.accepts("ip 10a0b0c1"));
+
+            let nfa = NFA::from("\\(a\\+b\\)\\|\\\\").unwrap();
+            assert!(nfa.accepts("(a+b)|\\"));
+            assert!(!nfa.accepts("aab"));
+
+            let nfa = NFA::from("\\x41\\u{e9}\\t").unwrap();
+            assert!(nfa.accepts("Aé\t"));
+        }
+
+        #[test]
+        fn class() {
+            let nfa = NFA::from("[a-c][0-9_]").unwrap();
+            assert!(nfa.accepts("b7"));
+            assert!(nfa.accepts("c_"));
+            assert!(!nfa.accepts("d7"));
+            assert!(!nfa.accepts("bx"));
+        }
+
+        #[test]
+        fn negated_class() {
+            let nfa = NFA::from("x[^0-9]+y").unwrap();
+            assert!(nfa.accepts("xaby"));
+            assert!(!nfa.accepts("xa1y"));
+            assert!(!nfa.accepts("xy"));
+        }
+
+        #[test]
+        fn optional() {
+            let nfa = NFA::from("colou?r").unwrap();
+            assert!(nfa.accepts("color"));
+            assert!(nfa.accepts("colour"));
+            assert!(!nfa.accepts("colouur"));
+        }
+
+        #[test]
+        fn repeat_exact() {
+            let nfa = NFA::from("x[0-9]{4}y").unwrap();
+            assert!(nfa.accepts("x2019y"));
+            assert!(!nfa.accepts("x201y"));
+            assert!(!nfa.accepts("x20191y"));
+        }
+
+        #[test]
+        fn repeat_bounded() {
+            let nfa = NFA::from("xa{2,3}y").unwrap();
+            assert!(!nfa.accepts("xay"));
+            assert!(nfa.accepts("xaay"));
+            assert!(nfa.accepts("xaaay"));
+            assert!(!nfa.accepts("xaaaay"));
+
+            let nfa = NFA::from("x(ab){,2}y").unwrap();
+            assert!(nfa.accepts("xy"));
+            assert!(nfa.accepts("xababy"));
+            assert!(!nfa.accepts("xabababy"));
+        }
+
+        #[test]
+        fn repeat_unbounded() {
+            let nfa = NFA::from("xa{2,}y").unwrap();
+            assert!(!nfa.accepts("xay"));
+            assert!(nfa.accepts("xaay"));
+            assert!(nfa.accepts("xaaaaaaay"));
+        }
+
+        #[test]
+        fn repeat_zero() {
+            let nfa = NFA::from("xa{0}y").unwrap();
+            assert!(nfa.accepts("xy"));
+            assert!(!nfa.accepts("xay"));
+        }
+
+        #[test]
+        fn repeat_bad_bounds() {
+            assert!(NFA::from("a{3,1}").is_err());
+        }
+
+        #[test]
+        fn anchors() {
+            let nfa = NFA::from("^ab$").unwrap();
+            assert!(nfa.accepts("ab"));
+            assert!(!nfa.accepts("abc"));
+
+            let nfa = NFA::from("(a|^b)c").unwrap();
+            assert!(nfa.accepts("bc"));
+            assert!(nfa.accepts("ac"));
+        }
+
+        #[test]
+        fn unanchored() {
+            let nfa = NFA::unanchored("unc").unwrap();
+            assert!(nfa.accepts("unc"));
+            assert!(nfa.accepts("bounce"));
+            assert!(!nfa.accepts("un c"));
+
+            let nfa = NFA::unanchored("a|b").unwrap();
+            assert!(nfa.accepts("cb"));
+            assert!(nfa.accepts("ac"));
+            assert!(!nfa.accepts("cd"));
+        }
+
+        #[test]
+        fn unanchored_line_start() {
+            let nfa = NFA::unanchored("^foo").unwrap();
+            assert!(nfa.accepts("foobar"));
+            assert!(!nfa.accepts("barfoo"));
+
+            let nfa = NFA::unanchored("x|^foo").unwrap();
+            assert!(nfa.accepts("barx"));
+            assert!(!nfa.accepts("barfoo"));
+        }
+
+        #[test]
+        fn unanchored_line_end() {
+            let nfa = NFA::unanchored("\\.rs$").unwrap();
+            assert!(nfa.accepts("main.rs"));
+            assert!(!nfa.accepts("main.rs.bak"));
+
+            let nfa = NFA::unanchored("^[a-z]+$").unwrap();
+            assert!(nfa.accepts("lowercase"));
+            assert!(!nfa.accepts("lower case"));
+        }
+
+        #[test]
+        fn word_boundaries() {
+            let nfa = NFA::unanchored("\\bid\\b").unwrap();
+            assert!(nfa.accepts("id"));
+            assert!(nfa.accepts("let id = 1;"));
+            assert!(!nfa.accepts("width"));
+            assert!(!nfa.accepts("id_x"));
+
+            let nfa = NFA::unanchored("\\Bid\\B").unwrap();
+            assert!(nfa.accepts("width"));
+            assert!(!nfa.accepts("an id"));
+
+            // a pattern may begin or end with a non-word char
+            let nfa = NFA::unanchored("\\b-x\\b").unwrap();
+            assert!(nfa.accepts("a-x"));
+            assert!(!nfa.accepts(" -x"));
+        }
+
+        #[test]
+        fn word_start_and_end() {
+            let nfa = NFA::unanchored("\\<the\\>").unwrap();
+            assert!(nfa.accepts("in the end"));
+            assert!(!nfa.accepts("other"));
+            assert!(!nfa.accepts("theme"));
+
+            let nfa = NFA::unanchored("\\>\\<").unwrap();
+            assert!(!nfa.accepts("a b"));
+            assert!(NFA::unanchored("é\\>").unwrap().accepts("café au lait"));
+        }
+
+        #[test]
+        fn empty_input() {
+            assert!(NFA::from("a*").unwrap().accepts(""));
+            assert!(NFA::from("^$").unwrap().accepts(""));
+            assert!(!NFA::from("a").unwrap().accepts(""));
+        }
+
+        #[test]
+        fn stray_bytes() {
+            // a Latin-1 é is one stray byte, which only wildcards, classes
+            // and \xNN match
+            assert!(NFA::from("caf.").unwrap().accepts(b"caf\xe9"));
+            assert!(NFA::from("caf[^a-z]").unwrap().accepts(b"caf\xe9"));
+            assert!(NFA::from("caf\\xe9").unwrap().accepts(b"caf\xe9"));
+            assert!(!NFA::from("caf\\xe9").unwrap().accepts("café"));
+            assert!(!NFA::from("caf[é]").unwrap().accepts(b"caf\xe9"));
+            assert!(NFA::from("caf\\x65").unwrap().accepts("cafe"));
+            assert!(NFA::from("caf[\\x80-\\xff]").unwrap().accepts(b"caf\xe9"));
+            assert!(!NFA::from("caf[\\x80-\\xff]").unwrap().accepts("café"));
+            assert!(!NFA::from("caf[^\\xe9]").unwrap().accepts(b"caf\xe9"));
+            assert!(NFA::from("caf[^\\xe9]").unwrap().accepts(b"caf\xea"));
+            assert!(NFA::from("caf[\\x00-\\xff]").unwrap().accepts("cafe"));
+            // a stray byte is not a word char
+            assert!(NFA::from("caf\\b").unwrap().accepts(b"caf\xe9"));
+        }
+
+        #[test]
+        fn case_insensitive() {
+            let flags = Flags {
+                case_insensitive: true,
+                ..Flags::default()
+            };
+            let nfa = NFA::with_flags("straße[a-c]", flags).unwrap();
+            assert!(nfa.accepts_full("STRAẞEb"));
+            assert!(nfa.accepts_full("StraßeC"));
+            assert!(!nfa.accepts_full("Strasse"));
+
+            let nfa = NFA::from("(?i)ΣΟΦΊΑ").unwrap();
+            assert!(nfa.accepts_full("σοφία"));
+            let nfa = NFA::from("x(?i:y)z").unwrap();
+            assert!(nfa.accepts_full("xYz"));
+            assert!(!nfa.accepts_full("xYZ"));
+        }
+
+        #[test]
+        fn full_match() {
+            let nfa = NFA::from("ab*").unwrap();
+            assert!(nfa.accepts("abbc"));
+            assert!(!nfa.accepts_full("abbc"));
+            assert!(nfa.accepts_full("abb"));
+            assert!(nfa.accepts_full("a"));
+            assert!(!nfa.accepts_full(""));
+
+            let nfa = NFA::from(".....").unwrap();
+            assert!(nfa.accepts("alimony"));
+            assert!(!nfa.accepts_full("alimony"));
+        }
+
+        #[test]
+        fn nested_closures() {
+            let nfa = NFA::from("(a*)*b").unwrap();
+            assert!(nfa.accepts("aaab"));
+            assert!(nfa.accepts("b"));
+            assert!(!nfa.accepts(&"a".repeat(10_000)));
+
+            let nfa = NFA::from("(a?){3}b").unwrap();
+            assert!(nfa.accepts("ab"));
+            assert!(nfa.accepts("aaab"));
+            assert!(!nfa.accepts("aaaab"));
         }
 
         #[test]
@@ -326,7 +743,7 @@ mod nfa {
             let ab = NFA::from("ab").unwrap();
             let cd = NFA::from("cd").unwrap();
             let abcd = ab + cd;
-            assert_eq!(true, abcd.accepts("abcd"));
+            assert!(abcd.accepts("abcd"));
         }
 
         #[test]
@@ -339,6 +756,32 @@ mod nfa {
             assert!(ab.accepts("ab"));
             assert!(ab.accepts("aabbb"));
         }
+
+        #[test]
+        fn add_multiple_patterns() {
+            // every pattern of the lhs leads on to every pattern of the rhs
+            let lhs = NFA::with_patterns(&["a", "bc"], Flags::default()).unwrap();
+            let rhs = NFA::with_patterns(&["x", "yz"], Flags::default()).unwrap();
+            let nfa = lhs + rhs;
+            for input in &["ax", "ayz", "bcx", "bcyz"] {
+                assert!(nfa.accepts_full(input), "{}", input);
+            }
+            for input in &["a", "bc", "x", "abcx", "ay"] {
+                assert!(!nfa.accepts_full(input), "{}", input);
+            }
+        }
+
+        #[test]
+        fn add_groups() {
+            let line = "aabc";
+            let nfa = NFA::from("(?P<a>a+)").unwrap() + NFA::from("(b)(?P<c>c)").unwrap();
+            assert_eq!(nfa.groups(), 3);
+            assert_eq!(nfa.group_names().collect::<Vec<_>>(), vec!["a", "c"]);
+            let caps = nfa.captures(line).unwrap();
+            assert_eq!(caps.name("a").unwrap().as_str(line), "aa");
+            assert_eq!(caps.get(2).unwrap().as_str(line), "b");
+            assert_eq!(caps.name("c").unwrap().as_str(line), "c");
+        }
     }
 
     mod generate {
@@ -347,52 +790,349 @@ mod nfa {
         #[test]
         fn gen_cat() {
             let nfa = NFA::from(".*ab.*").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
         }
 
         #[test]
         fn gen_alt() {
             let nfa = NFA::from(".*cab|t|kl.*").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
         }
 
         #[test]
         fn gen_kleenestar() {
             let nfa = NFA::from(".ha*t").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
         }
 
         #[test]
         fn gen_kleeneplus() {
             let nfa = NFA::from(".*ab.+").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
         }
 
         #[test]
         fn gen_any() {
             let nfa = NFA::from("........").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
         }
 
         #[test]
         fn gen_anykleene() {
             let nfa = NFA::from(".*.+").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
+        }
+
+        #[test]
+        fn gen_class() {
+            let nfa = NFA::from("[a-f]+[^a-z]*[\\u{3b1}-\\u{3c9}]").unwrap();
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
+        }
+
+        #[test]
+        fn gen_quantifiers() {
+            let nfa = NFA::from("ab?c{2}(de){1,3}f{0}g{2,}").unwrap();
+            for _ in 0..10 {
+                let gen_str = nfa.gen().unwrap();
+                assert!(nfa.accepts(&gen_str));
+            }
+        }
+
+        #[test]
+        fn gen_case_insensitive() {
+            let nfa = NFA::from("(?i)[a-z]{40}").unwrap();
+            let gen_str = String::from_utf8(nfa.gen().unwrap()).unwrap();
+            assert!(nfa.accepts_full(&gen_str));
+            assert!(gen_str.chars().any(|c| c.is_uppercase()));
+            assert!(gen_str.chars().any(|c| c.is_lowercase()));
         }
 
         #[test]
         fn gen_combo() {
             let nfa = NFA::from("(.+a.*.+((aa)*b|(e|d+)))|(x+h(i*|o*))").unwrap();
-            let gen_str = nfa.gen();
-            assert!(nfa.accepts(&format!("{}", gen_str)));
+            let gen_str = nfa.gen().unwrap();
+            assert!(nfa.accepts(&gen_str));
+        }
+
+        #[test]
+        fn gen_stray_byte() {
+            // no char is left, but the class still takes stray bytes
+            let nfa = NFA::from("[^\\x00-\\u{10ffff}]").unwrap();
+            let gen_str = nfa.gen().unwrap();
+            assert_eq!(gen_str.len(), 1);
+            assert!(nfa.accepts(&gen_str));
+
+            let nfa = NFA::from("[^\\x00-\\u{10ffff}\\x80-\\xff]").unwrap();
+            assert_eq!(nfa.gen(), Err(String::from("pattern matches nothing")));
+        }
+    }
+
+    mod nfa_find {
+        use super::*;
+
+        fn spans(pattern: &str, haystack: &str) -> Vec<(usize, usize)> {
+            NFA::from(pattern)
+                .unwrap()
+                .find_iter(haystack)
+                .map(|m| (m.start, m.end))
+                .collect()
+        }
+
+        #[test]
+        fn find_offsets() {
+            let nfa = NFA::from("b+").unwrap();
+            let found = nfa.find("aabbbc").unwrap();
+            assert_eq!(found.start, 2);
+            assert_eq!(found.end, 5);
+            assert_eq!(found.as_str("aabbbc"), "bbb");
+            assert!(nfa.find("aaa").is_none());
+        }
+
+        #[test]
+        fn find_char_offsets() {
+            let found = NFA::from("é+").unwrap().find("caféé!").unwrap();
+            assert_eq!((found.start, found.end), (3, 7));
+            assert_eq!((found.char_start, found.char_end), (3, 5));
+        }
+
+        #[test]
+        fn find_stray_bytes() {
+            let haystack = b"\xe9t\xe9 caf\xc3\xa9";
+            let found: Vec<(usize, usize)> = NFA::from("[^ ]+")
+                .unwrap()
+                .find_iter(haystack)
+                .map(|m| (m.start, m.end))
+                .collect();
+            assert_eq!(found, vec![(0, 3), (4, 9)]);
+            let found = NFA::from("\\xe9").unwrap().find(haystack).unwrap();
+            assert_eq!(found.as_bytes(haystack), b"\xe9");
+        }
+
+        #[test]
+        fn leftmost_longest() {
+            // leftmost wins over longer, then longest wins over first listed
+            assert_eq!(spans("a|bcd", "xbcda"), vec![(1, 4), (4, 5)]);
+            assert_eq!(spans("ab|abcd|abc", "abcde"), vec![(0, 4)]);
+            assert_eq!(spans("(a|ab)(c|bcd)", "abcd"), vec![(0, 4)]);
+        }
+
+        #[test]
+        fn find_iter_non_overlapping() {
+            assert_eq!(spans("aa", "aaaaa"), vec![(0, 2), (2, 4)]);
+            assert_eq!(spans("[0-9]+", "a1b22c333"), vec![(1, 2), (3, 5), (6, 9)]);
+        }
+
+        #[test]
+        fn find_iter_empty_matches() {
+            assert_eq!(spans("a*", "baab"), vec![(0, 0), (1, 3), (4, 4)]);
+            assert_eq!(spans("x*", "é"), vec![(0, 0), (2, 2)]);
+        }
+
+        #[test]
+        fn find_anchors() {
+            assert_eq!(spans("^a", "aaa"), vec![(0, 1)]);
+            assert_eq!(spans("a$", "aaa"), vec![(2, 3)]);
+        }
+
+        #[test]
+        fn find_words() {
+            assert_eq!(
+                spans("\\b[a-z]+\\b", "an id, width"),
+                vec![(0, 2), (3, 5), (7, 12)]
+            );
+            assert_eq!(spans("\\Bd", "id width"), vec![(1, 2), (5, 6)]);
+        }
+
+        #[test]
+        fn find_unanchored() {
+            let nfa = NFA::unanchored("b+").unwrap();
+            assert_eq!(nfa.find("abbc").map(|m| (m.start, m.end)), Some((1, 3)));
+        }
+    }
+
+    mod nfa_patterns {
+        use super::*;
+
+        fn patterns(patterns: &[&str]) -> NFA {
+            let flags = Flags {
+                unanchored: true,
+                ..Flags::default()
+            };
+            NFA::with_patterns(patterns, flags).unwrap()
+        }
+
+        #[test]
+        fn matches_any_pattern() {
+            let nfa = patterns(&["error", "^warn", "[0-9]{3}$"]);
+            assert!(nfa.accepts("disk error"));
+            assert!(nfa.accepts("warning: low"));
+            assert!(!nfa.accepts("a warning"));
+            assert!(nfa.accepts("status 404"));
+            assert!(!nfa.accepts("status 42"));
         }
 
+        #[test]
+        fn finds_across_patterns() {
+            let nfa = patterns(&["ab", "b+c"]);
+            let found: Vec<&str> = nfa
+                .find_iter("xabbbc bc")
+                .map(|m| m.as_str("xabbbc bc"))
+                .collect();
+            assert_eq!(found, vec!["ab", "bbc", "bc"]);
+        }
+
+        #[test]
+        fn numbers_groups_in_turn() {
+            let nfa = patterns(&["(?P<k>[a-z]+)=(?P<v>[0-9]+)", "(?P<v>[0-9]+)%"]);
+            assert_eq!(nfa.groups(), 3);
+            assert_eq!(nfa.group_names().collect::<Vec<_>>(), vec!["k", "v"]);
+            let line = "load 75%";
+            let caps = nfa.captures(line).unwrap();
+            assert_eq!(caps.name("v").unwrap().as_str(line), "75");
+            assert!(caps.name("k").is_none());
+        }
+
+        #[test]
+        fn no_patterns() {
+            let nfa = patterns(&[] as &[&str]);
+            assert!(!nfa.accepts(""));
+            assert!(!nfa.accepts("anything"));
+        }
+
+        #[test]
+        fn no_patterns_gen() {
+            let nfa = patterns(&[] as &[&str]);
+            assert_eq!(nfa.gen(), Err(String::from("pattern matches nothing")));
+        }
+
+        #[test]
+        fn names_bad_pattern() {
+            let flags = Flags::default();
+            assert_eq!(
+                NFA::with_patterns(&["a", "(b"], flags).unwrap_err(),
+                "Unexpected end of input at position 2 in pattern 2"
+            );
+        }
+
+        #[test]
+        fn whole_words() {
+            let flags = Flags {
+                unanchored: true,
+                whole_words: true,
+                ..Flags::default()
+            };
+            let nfa = NFA::with_patterns(&["id", "-x"], flags).unwrap();
+            assert!(nfa.accepts("id"));
+            assert!(nfa.accepts("width, id"));
+            assert!(!nfa.accepts("width"));
+            assert!(!nfa.accepts("id_1"));
+            // unlike \b, a pattern may start or end with a non-word char
+            assert!(nfa.accepts("a -x b"));
+            assert!(!nfa.accepts("a-xb"));
+            assert_eq!(nfa.find("width id").map(|m| m.start), Some(6));
+        }
+
+        #[test]
+        fn whole_line() {
+            let flags = Flags {
+                unanchored: true,
+                whole_line: true,
+                whole_words: true,
+                ..Flags::default()
+            };
+            let nfa = NFA::with_patterns(&["a+", "b|c"], flags).unwrap();
+            assert!(nfa.accepts("aaa"));
+            assert!(nfa.accepts("c"));
+            assert!(!nfa.accepts("aab"));
+            assert!(!nfa.accepts("bc"));
+            assert!(!nfa.accepts("xa"));
+            assert_eq!(nfa.find("aa").map(|m| (m.start, m.end)), Some((0, 2)));
+        }
+    }
+
+    mod nfa_captures {
+        use super::*;
+
+        fn spans(pattern: &str, haystack: &str) -> Vec<Option<(usize, usize)>> {
+            NFA::from(pattern)
+                .unwrap()
+                .captures(haystack)
+                .unwrap()
+                .iter()
+                .map(|group| group.map(|m| (m.start, m.end)))
+                .collect()
+        }
+
+        #[test]
+        fn captures_groups() {
+            assert_eq!(
+                spans("([a-z]+)=([0-9]+)", "set x=42;"),
+                vec![Some((4, 8)), Some((4, 5)), Some((6, 8))]
+            );
+        }
+
+        #[test]
+        fn captures_text() {
+            let line = "2024-01-05 ERROR disk full";
+            let nfa = NFA::from("([0-9]+)-([0-9]+)-([0-9]+) ([A-Z]+)").unwrap();
+            let caps = nfa.captures(line).unwrap();
+            assert_eq!(caps.get(1).unwrap().as_str(line), "2024");
+            assert_eq!(caps.get(4).unwrap().as_str(line), "ERROR");
+            assert!(caps.get(5).is_none());
+        }
+
+        #[test]
+        fn captures_unmatched_group() {
+            assert_eq!(
+                spans("(a)|(b)", "b"),
+                vec![Some((0, 1)), None, Some((0, 1))]
+            );
+        }
+
+        #[test]
+        fn captures_last_iteration() {
+            assert_eq!(spans("(a|b)*", "abba"), vec![Some((0, 4)), Some((3, 4))]);
+        }
+
+        #[test]
+        fn captures_nested() {
+            assert_eq!(
+                spans("((a)(b))c", "xabc"),
+                vec![Some((1, 4)), Some((1, 3)), Some((1, 2)), Some((2, 3))]
+            );
+        }
+
+        #[test]
+        fn captures_none() {
+            assert!(NFA::from("(a)").unwrap().captures("b").is_none());
+            assert_eq!(NFA::from("(a)((b)c)").unwrap().groups(), 3);
+        }
+
+        #[test]
+        fn captures_by_name() {
+            let line = "user=root host=db1";
+            let nfa = NFA::from("user=(?P<user>[a-z]+) host=(?<host>[a-z0-9]+)").unwrap();
+            let caps = nfa.captures(line).unwrap();
+            assert_eq!(caps.name("user").unwrap().as_str(line), "root");
+            assert_eq!(caps.name("host").unwrap().as_str(line), "db1");
+            assert!(caps.name("port").is_none());
+            assert_eq!(nfa.group_names().collect::<Vec<_>>(), vec!["user", "host"]);
+        }
+
+        #[test]
+        fn captures_unanchored() {
+            let nfa = NFA::unanchored("x(y+)").unwrap();
+            let caps = nfa.captures("axyyz").unwrap();
+            assert_eq!(caps.get(1).map(|m| (m.start, m.end)), Some((2, 4)));
+            assert!(nfa.accepts("axyyz"));
+        }
     }
 }
 
@@ -406,24 +1146,182 @@ type StateId = usize;
  * - Start is starting state
  * - Match is a state with a single matching transition out
  * - Split is a state with two epsilon transitions out
- * - End is the final accepting state
+ * - Assert is an epsilon transition guarded by a Look
+ * - Save is an epsilon transition that records the position in a slot,
+ *   2n where group n opens and 2n + 1 where it closes
+ * - End is a final accepting state, tagged with the index of the pattern
+ *   it accepts when several are compiled together
  */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum State {
     Start(Option<StateId>),
     Match(Char, Option<StateId>),
     Split(Option<StateId>, Option<StateId>),
-    End,
+    Assert(Look, Option<StateId>),
+    Save(usize, Option<StateId>),
+    End(usize),
+}
+
+/**
+ * Looks are the zero-width conditions of Assert states. They are
+ * decided by the characters on either side of the current position,
+ * with None past either end of the input.
+ */
+#[derive(Debug, Clone)]
+enum Look {
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    WordStart,
+    WordEnd,
+    // the halves of a word boundary that whole_words puts around a
+    // pattern: no word char just before, and no word char just after
+    WordStartHalf,
+    WordEndHalf,
+}
+
+impl Look {
+    fn holds(&self, prev: Option<Unit>, next: Option<Unit>) -> bool {
+        let (before, after) = (is_word(prev), is_word(next));
+        match self {
+            Look::LineStart => prev.is_none(),
+            Look::LineEnd => next.is_none(),
+            Look::WordBoundary => before != after,
+            Look::NotWordBoundary => before == after,
+            Look::WordStart => !before && after,
+            Look::WordEnd => before && !after,
+            Look::WordStartHalf => !before,
+            Look::WordEndHalf => !after,
+        }
+    }
+
+    /**
+     * Whether the Look depends on which chars are around it rather than
+     * only on whether there are any, which the DFAs can't tell.
+     */
+    fn is_word(&self) -> bool {
+        !matches!(self, Look::LineStart | Look::LineEnd)
+    }
+}
+
+/**
+ * Word chars are letters, digits and underscores, as in egrep's \w.
+ * There are none past either end of the input, and a stray byte is not
+ * one.
+ */
+fn is_word(unit: Option<Unit>) -> bool {
+    matches!(unit, Some(Unit::Char(c)) if c.is_alphanumeric() || c == '_')
 }
 
 /**
  * Chars are the matching label of a non-epsilon edge in the
- * transition diagram representation of the NFA.
+ * transition diagram representation of the NFA. A stray byte of the
+ * input, one that is not part of any char, is matched by Any, by a
+ * Byte of the same value, and by a class that lists it or, if negated,
+ * does not.
  */
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum Char {
     Literal(char),
     Any,
+    Class(bool, Vec<(char, char)>, Vec<(u8, u8)>),
+    Byte(u8),
+}
+
+impl Char {
+    /**
+     * Does this label accept the input character?
+     */
+    fn matches(&self, unit: Unit) -> bool {
+        match (self, unit) {
+            (Char::Literal(literal), Unit::Char(c)) => *literal == c,
+            (Char::Any, _) => true,
+            (Char::Class(negated, ranges, _), Unit::Char(c)) => {
+                ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+            }
+            (Char::Class(negated, _, bytes), Unit::Byte(b)) => {
+                bytes.iter().any(|&(lo, hi)| lo <= b && b <= hi) != *negated
+            }
+            (Char::Byte(byte), Unit::Byte(b)) => *byte == b,
+            _ => false,
+        }
+    }
+
+    /**
+     * The units this label mentions, as inclusive ranges of their keys.
+     * A negated class mentions the chars it excludes.
+     */
+    fn ranges(&self) -> Vec<(u32, u32)> {
+        match self {
+            Char::Literal(c) => vec![(*c as u32, *c as u32)],
+            Char::Any => vec![],
+            Char::Class(_, ranges, bytes) => ranges
+                .iter()
+                .map(|(lo, hi)| (*lo as u32, *hi as u32))
+                .chain(
+                    bytes
+                        .iter()
+                        .map(|(lo, hi)| (Unit::Byte(*lo).key(), Unit::Byte(*hi).key())),
+                )
+                .collect(),
+            Char::Byte(b) => {
+                let key = Unit::Byte(*b).key();
+                vec![(key, key)]
+            }
+        }
+    }
+
+    /**
+     * Pick a random character accepted by this label. Wildcards and
+     * negated classes prefer alphanumerics so generated strings stay
+     * readable. A class that accepts nothing at all is an error.
+     */
+    fn sample(&self) -> Result<Unit, String> {
+        let mut rng = thread_rng();
+        let c = match self {
+            Char::Literal(c) => *c,
+            Char::Any => rng.sample(Alphanumeric),
+            Char::Class(false, ranges, bytes) => loop {
+                if ranges.is_empty() && bytes.is_empty() {
+                    return Err(String::from("pattern matches nothing"));
+                }
+                let pick = rng.gen_range(0, ranges.len() + bytes.len());
+                if pick >= ranges.len() {
+                    let (lo, hi) = bytes[pick - ranges.len()];
+                    return Ok(Unit::Byte(rng.gen_range(lo as u16, hi as u16 + 1) as u8));
+                }
+                let (lo, hi) = ranges[pick];
+                let code = rng.gen_range(lo as u32, hi as u32 + 1);
+                // ranges spanning the surrogate gap can land on a non-char
+                if let Some(c) = std::char::from_u32(code) {
+                    break c;
+                }
+            },
+            Char::Class(true, ..) => {
+                let found = (0..32)
+                    .map(|_| rng.sample(Alphanumeric))
+                    .chain((0..=0x10FFFF).filter_map(std::char::from_u32))
+                    .find(|c| self.matches(Unit::Char(*c)));
+                match found {
+                    Some(c) => c,
+                    // a class excluding every char may still take a stray byte
+                    None => {
+                        let strays: Vec<Unit> = (0x80..=0xFF)
+                            .map(Unit::Byte)
+                            .filter(|unit| self.matches(*unit))
+                            .collect();
+                        return strays
+                            .choose(&mut rng)
+                            .copied()
+                            .ok_or_else(|| String::from("pattern matches nothing"));
+                    }
+                }
+            }
+            Char::Byte(b) => return Ok(Unit::Byte(*b)),
+        };
+        Ok(Unit::Char(c))
+    }
 }
 
 /**
@@ -448,7 +1346,156 @@ impl NFA {
         NFA {
             states: vec![],
             start: 0,
+            body: 0,
+            groups: 0,
+            names: vec![],
+        }
+    }
+
+    /**
+     * Compile patterns as alternatives, optionally behind a loop that lets
+     * the match begin after any number of input characters.
+     */
+    fn build<S: AsRef<str>>(patterns: &[S], flags: Flags) -> Result<NFA, String> {
+        let mut nfa = NFA::new();
+
+        let start = nfa.add_state(Start(None));
+        nfa.start = start;
+
+        // The "body" of the NFA is made of the states between Start and the
+        // End of each pattern
+        let mut body = None;
+        let mut ends = vec![];
+        for (i, pattern) in patterns.iter().enumerate() {
+            // Parse the Abstract Syntax Tree of the Regular Expression
+            let tokens = Tokenizer::new(pattern.as_ref());
+            let ast =
+                Parser::parse_after(tokens, flags.case_insensitive, nfa.groups).map_err(|msg| {
+                    match patterns.len() {
+                        1 => msg,
+                        _ => format!("{} in pattern {}", msg, i + 1),
+                    }
+                })?;
+            let mut fragment = nfa.gen_fragment(&ast);
+            if flags.whole_line {
+                fragment = nfa.gen_between(Look::LineStart, fragment, Look::LineEnd);
+            } else if flags.whole_words {
+                fragment = nfa.gen_between(Look::WordStartHalf, fragment, Look::WordEndHalf);
+            }
+            ends.push(fragment.ends.clone());
+            body = Some(match body {
+                Some(alternatives) => nfa.gen_alternation(alternatives, fragment),
+                None => fragment,
+            });
+        }
+        // an empty class can never match, so neither can no patterns
+        let body = body.unwrap_or_else(|| nfa.gen_fragment(&AST::Class(false, vec![], vec![])));
+        nfa.body = body.start;
+        // a whole line match can only start at the start
+        if flags.unanchored && !flags.whole_line {
+            let skip = nfa.add_state(Match(Char::Any, None));
+            let split = nfa.add_state(Split(Some(body.start), Some(skip)));
+            nfa.join(skip, split);
+            nfa.join(nfa.start, split);
+        } else {
+            nfa.join(nfa.start, body.start);
+        }
+
+        if ends.is_empty() {
+            ends.push(body.ends.clone());
+        }
+        for (tag, ends) in ends.into_iter().enumerate() {
+            let end = nfa.add_state(End(tag));
+            for id in ends {
+                nfa.join(id, end);
+            }
+        }
+
+        Ok(nfa)
+    }
+
+    /**
+     * Thompson's simulation tracks the set of states the NFA could be in
+     * after each character. The epsilon closure is taken once per step
+     * and each state enters a set at most once, so the running time is
+     * O(input * states) with no backtracking. Prefix matching succeeds as
+     * soon as End is reachable; full matching waits for the input to end.
+     */
+    fn simulate(&self, input: &[u8], full: bool) -> bool {
+        let mut current = SparseSet::new(self.states.len());
+        let mut next = SparseSet::new(self.states.len());
+        let mut stack = Vec::new();
+        let mut chars = Units::new(input).peekable();
+        self.add_closure(
+            &mut current,
+            &mut stack,
+            self.start,
+            None,
+            chars.peek().cloned(),
+        );
+        while let Some(ch) = chars.next() {
+            if current.is_empty() {
+                return false;
+            }
+            if !full && self.is_accepting(&current) {
+                return true;
+            }
+            next.clear();
+            let lookahead = chars.peek().cloned();
+            for id in current.iter() {
+                if let Match(c, Some(to)) = &self.states[*id] {
+                    if c.matches(ch) {
+                        self.add_closure(&mut next, &mut stack, *to, Some(ch), lookahead);
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
         }
+        self.is_accepting(&current)
+    }
+
+    /**
+     * Add a state and everything reachable from it by epsilon transitions
+     * to a set. Assertions are decided by the characters on either side
+     * of the position being closed over. Lhs branches are explored first.
+     */
+    fn add_closure(
+        &self,
+        set: &mut SparseSet,
+        stack: &mut Vec<StateId>,
+        id: StateId,
+        prev: Option<Unit>,
+        next: Option<Unit>,
+    ) {
+        stack.push(id);
+        while let Some(id) = stack.pop() {
+            if !set.insert(id) {
+                continue;
+            }
+            match &self.states[id] {
+                Start(Some(to)) => stack.push(*to),
+                Split(Some(lhs), Some(rhs)) => {
+                    stack.push(*rhs);
+                    stack.push(*lhs);
+                }
+                Assert(look, Some(to)) if look.holds(prev, next) => stack.push(*to),
+                Save(_, Some(to)) => stack.push(*to),
+                _ => {}
+            }
+        }
+    }
+
+    /**
+     * The search behind `find`, beginning at a byte offset of the
+     * haystack that is char_start chars in.
+     */
+    fn find_at(&self, haystack: &[u8], start: usize, char_start: usize) -> Option<Match> {
+        let groups = self.search(haystack, start, char_start, 1)?;
+        groups[0]
+    }
+
+    fn is_accepting(&self, set: &SparseSet) -> bool {
+        set.iter().any(|id| matches!(self.states[*id], End(_)))
     }
 
     /**
@@ -480,6 +1527,21 @@ impl NFA {
                     ends: vec![state],
                 }
             }
+            AST::Byte(b) => {
+                let state = self.add_state(Match(Char::Byte(*b), None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
+            AST::Class(negated, ranges, bytes) => {
+                let class = Char::Class(*negated, ranges.clone(), bytes.clone());
+                let state = self.add_state(Match(class, None));
+                Fragment {
+                    start: state,
+                    ends: vec![state],
+                }
+            }
             AST::Catenation(lhs, rhs) => {
                 let left = self.gen_fragment(lhs);
                 let right = self.gen_fragment(rhs);
@@ -492,22 +1554,30 @@ impl NFA {
             AST::Alternation(lhs, rhs) => {
                 let left = self.gen_fragment(lhs);
                 let right = self.gen_fragment(rhs);
-                let splitstate = self.add_state(Split(Some(left.start), Some(right.start)));
-                Fragment {
-                    start: splitstate,
-                    ends: [left.ends.as_slice(), right.ends.as_slice()].concat(),
-                }
+                self.gen_alternation(left, right)
             }
-            AST::Closure(lhs) => {
-                let kleenestar = self.gen_fragment(lhs);
-                let split = self.add_state(Split(Some(kleenestar.start), None));
-                // want kleenestar (the pattern repeated) to point back to split
-                self.join_fragment(&kleenestar, split);
+            AST::LineStart => self.gen_assert(Look::LineStart),
+            AST::LineEnd => self.gen_assert(Look::LineEnd),
+            AST::WordBoundary => self.gen_assert(Look::WordBoundary),
+            AST::NotWordBoundary => self.gen_assert(Look::NotWordBoundary),
+            AST::WordStart => self.gen_assert(Look::WordStart),
+            AST::WordEnd => self.gen_assert(Look::WordEnd),
+            AST::Group(index, name, lhs) => {
+                self.groups = self.groups.max(*index);
+                if let Some(name) = name {
+                    self.names.push((name.clone(), *index));
+                }
+                let open = self.add_state(Save(2 * index, None));
+                let group = self.gen_fragment(lhs);
+                let close = self.add_state(Save(2 * index + 1, None));
+                self.join(open, group.start);
+                self.join_fragment(&group, close);
                 Fragment {
-                    start: split,
-                    ends: vec![split],
+                    start: open,
+                    ends: vec![close],
                 }
             }
+            AST::Closure(lhs) => self.gen_closure(lhs),
             AST::OneOrMore(lhs) => {
                 let kleeneplus = self.gen_fragment(lhs);
                 let split = self.add_state(Split(Some(kleeneplus.start), None));
@@ -517,7 +1587,137 @@ impl NFA {
                     ends: vec![split],
                 }
             }
-            node => panic!("Unimplemented branch of gen_fragment: {:?}", node),
+            AST::Optional(lhs) => {
+                let optional = self.gen_fragment(lhs);
+                let split = self.add_state(Split(Some(optional.start), None));
+                Fragment {
+                    start: split,
+                    ends: [optional.ends.as_slice(), &[split]].concat(),
+                }
+            }
+            AST::Repeat(lhs, min, max) => {
+                // min required copies of lhs, then a closure when unbounded or
+                // max - min nested optional copies, so e{1,3} is e(e(e)?)?
+                let mut repeat = None;
+                for _ in 0..*min {
+                    let copy = self.gen_fragment(lhs);
+                    repeat = Some(self.catenate(repeat, copy));
+                }
+                match max {
+                    None => {
+                        let closure = self.gen_closure(lhs);
+                        repeat = Some(self.catenate(repeat, closure));
+                    }
+                    Some(max) => {
+                        let mut skips = vec![];
+                        for _ in *min..*max {
+                            let copy = self.gen_fragment(lhs);
+                            let split = self.add_state(Split(Some(copy.start), None));
+                            let optional = Fragment {
+                                start: split,
+                                ends: copy.ends,
+                            };
+                            repeat = Some(self.catenate(repeat, optional));
+                            skips.push(split);
+                        }
+                        if let Some(ref mut repeat) = repeat {
+                            repeat.ends.extend(skips);
+                        }
+                    }
+                }
+                // e{0} and e{0,0} match only the empty string
+                repeat.unwrap_or_else(|| self.gen_empty())
+            }
+            AST::Empty => self.gen_empty(),
+        }
+    }
+
+    /**
+     * A fragment that matches only the empty string.
+     */
+    fn gen_empty(&mut self) -> Fragment {
+        let empty = self.add_state(Split(None, None));
+        Fragment {
+            start: empty,
+            ends: vec![empty],
+        }
+    }
+
+    /**
+     * An assertion is a single state that passes through when it holds.
+     */
+    fn gen_assert(&mut self, look: Look) -> Fragment {
+        let state = self.add_state(Assert(look, None));
+        Fragment {
+            start: state,
+            ends: vec![state],
+        }
+    }
+
+    /**
+     * Surround a fragment with an assertion on either side.
+     */
+    fn gen_between(&mut self, before: Look, fragment: Fragment, after: Look) -> Fragment {
+        let before = self.gen_assert(before);
+        let after = self.gen_assert(after);
+        self.join_fragment(&before, fragment.start);
+        self.join_fragment(&fragment, after.start);
+        Fragment {
+            start: before.start,
+            ends: after.ends,
+        }
+    }
+
+    /**
+     * Whether any assertion needs to see the chars around it, so that
+     * only the NFA and Pike VM can decide it.
+     */
+    fn has_word_assertions(&self) -> bool {
+        self.states
+            .iter()
+            .any(|state| matches!(state, Assert(look, _) if look.is_word()))
+    }
+
+    /**
+     * An alternation splits to either fragment, preferring the left, and
+     * its loose ends are theirs together.
+     */
+    fn gen_alternation(&mut self, left: Fragment, right: Fragment) -> Fragment {
+        let splitstate = self.add_state(Split(Some(left.start), Some(right.start)));
+        Fragment {
+            start: splitstate,
+            ends: [left.ends.as_slice(), right.ends.as_slice()].concat(),
+        }
+    }
+
+    /**
+     * A Kleene closure loops through a Split state which is both the start
+     * and the only loose end of the fragment.
+     */
+    fn gen_closure(&mut self, ast: &AST) -> Fragment {
+        let kleenestar = self.gen_fragment(ast);
+        let split = self.add_state(Split(Some(kleenestar.start), None));
+        // want kleenestar (the pattern repeated) to point back to split
+        self.join_fragment(&kleenestar, split);
+        Fragment {
+            start: split,
+            ends: vec![split],
+        }
+    }
+
+    /**
+     * Append a fragment to an optional fragment built so far.
+     */
+    fn catenate(&mut self, lhs: Option<Fragment>, rhs: Fragment) -> Fragment {
+        match lhs {
+            Some(lhs) => {
+                self.join_fragment(&lhs, rhs.start);
+                Fragment {
+                    start: lhs.start,
+                    ends: rhs.ends,
+                }
+            }
+            None => rhs,
         }
     }
 
@@ -534,88 +1734,67 @@ impl NFA {
      * Join a loose end of one state to another by IDs.
      * Note in the Split case, only the 2nd ID (rhs) is being bound.
      * It is assumed when building an NFA with these constructs
-     * that the lhs of an Split state will always be known and bound.
+     * that the lhs of an Split state will always be known and bound,
+     * except for the empty fragment, a Split with both sides loose.
      */
     fn join(&mut self, from: StateId, to: StateId) {
         match self.states[from] {
             Start(ref mut next) => *next = Some(to),
             Match(_, ref mut next) => *next = Some(to),
-            Split(_, ref mut next) => *next = Some(to),
-            End => {}
-        }
-    }
-
-    fn parse_state(&self, mut current: &mut Vec<StateId>, mut next_states: &mut Vec<StateId>) {
-        // recurse a list of ids from split states until it hits a match or end state
-        // parse_split takes in vectors and returns updated versions of them
-        // split_states is a list of the StateIDs states point to, NOT actual split states
-        // return the vector with next
-        for next_id in (&next_states).iter() {
-            match &self.states[*next_id] {
-                State::Start(id) => {
-                    current.push(*next_id);
-                }
-                State::Match(match_char, id) => {
-                    current.push(*next_id);
-                }
-                State::Split(id1, id2) => {
-                    let mut left_tree_splits = Vec::new();
-                    left_tree_splits.push(id1.unwrap());
-                    &self.parse_state(&mut current, &mut left_tree_splits);
-                    let mut right_tree_splits = Vec::new();
-                    right_tree_splits.push(id2.unwrap());
-                    &self.parse_state(&mut current, &mut right_tree_splits);
-                }
-                State::End => {
-                    current.push(*next_id);
-                }
+            Split(ref mut lhs, ref mut next) => {
+                lhs.get_or_insert(to);
+                *next = Some(to);
             }
+            Assert(_, ref mut next) => *next = Some(to),
+            Save(_, ref mut next) => *next = Some(to),
+            End(_) => {}
         }
-        next_states.clear();
     }
 }
 
+/**
+ * Catenate two NFAs: every end of the lhs, one per pattern it was built
+ * from, leads on to the rhs, and the rhs ends keep their tags. The groups
+ * of the rhs are numbered after those of the lhs.
+ */
 impl Add for NFA {
     type Output = NFA;
 
     fn add(self, rhs: NFA) -> NFA {
-        let mut added_states = Vec::new();
-        // clone left hand side (self)
-        // end index is now start of second NFA, if start is 0, then it is now the index of the end
-        // state, which is the size - 1
-        // joint NFA will include start state from RHS
-        // add rhs to NFA array
-        for state in self.states {
-            match state {
-                State::Start(id) => added_states.push(State::Start(id)),
-                State::Match(c, id) => added_states.push(State::Match(c, id)),
-                State::Split(id1, id2) => added_states.push(State::Split(id1, id2)),
-                State::End => {} // do not add
-            }
-        }
-
-        let end_id = added_states.len(); // end is always last element on vector
-        for rhstate in rhs.states {
-            match rhstate {
-                State::Start(id) => {
-                    let rhs_id = id.unwrap() + end_id;
-                    added_states.push(State::Start(Some(rhs_id)));
-                }
-                State::Match(c, id) => {
-                    let rhs_id = id.unwrap() + end_id;
-                    added_states.push(State::Match(c, Some(rhs_id)));
-                }
-                State::Split(id1, id2) => {
-                    let rhs_id1 = id1.unwrap() + end_id;
-                    let rhs_id2 = id2.unwrap() + end_id;
-                    added_states.push(State::Split(Some(rhs_id1), Some(rhs_id2)));
-                }
-                State::End => added_states.push(State::End),
-            }
-        }
+        let offset = self.states.len();
+        let groups = self.groups;
+        // group n saves to slots 2n and 2n + 1
+        let slots = groups + groups;
+        let shift = |id: Option<StateId>| id.map(|id| id + offset);
+        // an lhs end becomes a pass-through to where the rhs starts
+        let mut states: Vec<State> = self
+            .states
+            .into_iter()
+            .map(|state| match state {
+                End(_) => Start(Some(offset + rhs.start)),
+                state => state,
+            })
+            .collect();
+        states.extend(rhs.states.into_iter().map(|state| match state {
+            Start(id) => Start(shift(id)),
+            Match(c, id) => Match(c, shift(id)),
+            Split(lhs, rhs) => Split(shift(lhs), shift(rhs)),
+            Assert(look, id) => Assert(look, shift(id)),
+            Save(slot, id) => Save(slot + slots, shift(id)),
+            End(tag) => End(tag),
+        }));
+        let mut names = self.names;
+        names.extend(
+            rhs.names
+                .into_iter()
+                .map(|(name, index)| (name, index + groups)),
+        );
         NFA {
-            start: 0,
-            states: added_states,
+            start: self.start,
+            body: self.body,
+            groups: groups + rhs.groups,
+            names,
+            states,
         }
     }
 }
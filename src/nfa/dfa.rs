@@ -0,0 +1,655 @@
+use super::sparse::SparseSet;
+use super::utf8::{Unit, Units, STRAY_KEYS};
+use super::State::*;
+use super::{StateId, NFA};
+use std::collections::HashMap;
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - DFA
+ *
+ * A DFA is compiled from an NFA by the subset construction: each DFA
+ * state stands for the set of NFA states the simulation could be in.
+ * Matching is then one table lookup per input character.
+ */
+
+/**
+ * Full subset construction is exponential in the worst case, so give up
+ * rather than exhaust memory on patterns like .*a.........
+ */
+const STATE_LIMIT: usize = 10_000;
+
+/**
+ * The dead state has an empty NFA set and loops to itself.
+ */
+pub(super) const DEAD: usize = 0;
+
+/**
+ * The characters are partitioned into equivalence classes, intervals of
+ * chars that every edge label of the NFA treats the same way. Transition
+ * tables are indexed by class rather than by char. Stray bytes of the
+ * input are keyed in the surrogate gap and always kept out of the
+ * classes of chars.
+ */
+#[derive(Clone, Debug)]
+pub struct Alphabet {
+    // the first char of each class, in order; bounds[0] is always 0
+    bounds: Vec<u32>,
+    // the class of every ASCII char, to skip the binary search
+    ascii: Vec<usize>,
+}
+
+impl Alphabet {
+    pub fn new(nfa: &NFA) -> Alphabet {
+        let mut bounds = vec![0, STRAY_KEYS.0, STRAY_KEYS.1 + 1];
+        for state in &nfa.states {
+            if let Match(c, _) = state {
+                for (lo, hi) in c.ranges() {
+                    bounds.push(lo);
+                    bounds.push(hi + 1);
+                }
+            }
+        }
+        bounds.sort_unstable();
+        bounds.dedup();
+        bounds.retain(|bound| *bound <= std::char::MAX as u32);
+        let mut alphabet = Alphabet {
+            bounds,
+            ascii: vec![],
+        };
+        alphabet.ascii = (0..128).map(|key| alphabet.search(key)).collect();
+        alphabet
+    }
+
+    /**
+     * The number of equivalence classes.
+     */
+    pub fn classes(&self) -> usize {
+        self.bounds.len()
+    }
+
+    /**
+     * The equivalence class of a char or stray byte.
+     */
+    pub(super) fn class(&self, unit: Unit) -> usize {
+        match unit {
+            Unit::Char(c) if c.is_ascii() => self.ascii[c as usize],
+            unit => self.search(unit.key()),
+        }
+    }
+
+    fn search(&self, key: u32) -> usize {
+        match self.bounds.binary_search(&key) {
+            Ok(class) => class,
+            Err(next) => next - 1,
+        }
+    }
+
+    /**
+     * Some char or stray byte in a class, or None for a class lying in
+     * the part of the surrogate gap that no input can reach.
+     */
+    pub(super) fn representative(&self, class: usize) -> Option<Unit> {
+        let (lo, hi) = self.range(class);
+        (lo..=hi).find_map(Unit::from_key)
+    }
+
+    /**
+     * The first and last keys of a class. Either may fall in the
+     * surrogate gap.
+     */
+    pub fn range(&self, class: usize) -> (u32, u32) {
+        let hi = match self.bounds.get(class + 1) {
+            Some(next) => next - 1,
+            None => std::char::MAX as u32,
+        };
+        (self.bounds[class], hi)
+    }
+}
+
+/**
+ * A DFA is a dense transition table with a row per state and a column
+ * per equivalence class of the alphabet.
+ */
+#[derive(Debug)]
+pub struct DFA {
+    pub(super) alphabet: Alphabet,
+    pub(super) start: usize,
+    pub(super) transitions: Vec<usize>,
+    // End is reachable without looking past the current position
+    pub(super) accepting: Vec<bool>,
+    // End is reachable once the input has ended, which satisfies $
+    pub(super) accepting_at_end: Vec<bool>,
+}
+
+impl DFA {
+    /**
+     * Construct a DFA from an NFA by the subset construction. Word
+     * boundaries depend on the next char, which a DFA state can't know,
+     * so an NFA with any is refused.
+     */
+    pub fn new(nfa: &NFA) -> Result<DFA, String> {
+        if nfa.has_word_assertions() {
+            return Err(String::from("DFA can't decide word boundaries"));
+        }
+        let alphabet = Alphabet::new(nfa);
+        let classes = alphabet.classes();
+        let mut dfa = DFA {
+            alphabet,
+            start: DEAD,
+            transitions: vec![DEAD; classes],
+            accepting: vec![false],
+            accepting_at_end: vec![false],
+        };
+        let mut scratch = Scratch::new(nfa);
+        let mut subsets = vec![Subset::dead()];
+        let mut ids = HashMap::new();
+
+        let start = Subset::start(nfa, &mut scratch);
+        dfa.start = dfa.intern(nfa, &mut scratch, &mut subsets, &mut ids, start)?;
+
+        // states are numbered in the order they are found, so walking the
+        // ids visits each state once as the table grows behind us
+        let mut id = DEAD + 1;
+        while id < subsets.len() {
+            for class in 0..classes {
+                let c = match dfa.alphabet.representative(class) {
+                    Some(c) => c,
+                    None => continue,
+                };
+                let next = subsets[id].step(nfa, &mut scratch, c);
+                let next = dfa.intern(nfa, &mut scratch, &mut subsets, &mut ids, next)?;
+                dfa.transitions[id * classes + class] = next;
+            }
+            id += 1;
+        }
+        Ok(dfa)
+    }
+
+    /**
+     * Given an input string, run the DFA to determine if some prefix of
+     * the input is accepted, with the same meaning as `NFA::accepts`.
+     */
+    pub fn accepts<I: AsRef<[u8]> + ?Sized>(&self, input: &I) -> bool {
+        let classes = self.alphabet.classes();
+        let mut state = self.start;
+        for c in Units::new(input.as_ref()) {
+            if self.accepting[state] {
+                return true;
+            }
+            state = self.transitions[state * classes + self.alphabet.class(c)];
+            if state == DEAD {
+                return false;
+            }
+        }
+        self.accepting_at_end[state]
+    }
+
+    /**
+     * Given an input string, run the DFA to determine if the whole input
+     * is accepted, with the same meaning as `NFA::accepts_full`.
+     */
+    pub fn accepts_full<I: AsRef<[u8]> + ?Sized>(&self, input: &I) -> bool {
+        let classes = self.alphabet.classes();
+        let mut state = self.start;
+        for c in Units::new(input.as_ref()) {
+            state = self.transitions[state * classes + self.alphabet.class(c)];
+            if state == DEAD {
+                return false;
+            }
+        }
+        self.accepting_at_end[state]
+    }
+
+    /**
+     * The number of states, including the dead state.
+     */
+    pub fn states(&self) -> usize {
+        self.accepting.len()
+    }
+
+    /**
+     * Hopcroft's algorithm: merge states no input can tell apart. The
+     * result is numbered breadth first from the start state, so patterns
+     * for the same language produce the same table.
+     */
+    pub fn minimize(&self) -> DFA {
+        let classes = self.alphabet.classes();
+        let states = self.states();
+
+        // the sources of the transitions into each state, by class
+        let mut sources = vec![vec![]; states * classes];
+        for from in 0..states {
+            for class in 0..classes {
+                let to = self.transitions[from * classes + class];
+                sources[to * classes + class].push(from);
+            }
+        }
+
+        // states that accept differently can never be merged
+        let mut blocks: Vec<Vec<usize>> = vec![];
+        let mut block_of = vec![0; states];
+        let mut kinds = HashMap::new();
+        for (state, block_of_state) in block_of.iter_mut().enumerate() {
+            let kind = (self.accepting[state], self.accepting_at_end[state]);
+            let block = *kinds.entry(kind).or_insert_with(|| {
+                blocks.push(vec![]);
+                blocks.len() - 1
+            });
+            blocks[block].push(state);
+            *block_of_state = block;
+        }
+
+        let mut worklist: Vec<usize> = (0..blocks.len()).collect();
+        let mut marked = vec![false; states];
+        while let Some(splitter) = worklist.pop() {
+            let splitter = blocks[splitter].clone();
+            for class in 0..classes {
+                // mark the states with a transition on class into splitter
+                let mut touched = vec![];
+                for to in &splitter {
+                    for from in &sources[to * classes + class] {
+                        if !marked[*from] {
+                            marked[*from] = true;
+                            touched.push(block_of[*from]);
+                        }
+                    }
+                }
+                touched.sort_unstable();
+                touched.dedup();
+                // split each touched block into its marked and unmarked states
+                for block in touched {
+                    let (inside, outside): (Vec<usize>, Vec<usize>) =
+                        blocks[block].iter().partition(|state| marked[**state]);
+                    if outside.is_empty() {
+                        continue;
+                    }
+                    // the smaller half joins the worklist; if the block was
+                    // already waiting, the larger half keeps its place
+                    let (larger, smaller) = if inside.len() > outside.len() {
+                        (inside, outside)
+                    } else {
+                        (outside, inside)
+                    };
+                    let new = blocks.len();
+                    for state in &smaller {
+                        block_of[*state] = new;
+                    }
+                    blocks[block] = larger;
+                    blocks.push(smaller);
+                    worklist.push(new);
+                }
+                for to in &splitter {
+                    for from in &sources[to * classes + class] {
+                        marked[*from] = false;
+                    }
+                }
+            }
+        }
+
+        // renumber with the dead block first, then breadth first from start
+        let mut ids = vec![None; blocks.len()];
+        let mut order = vec![];
+        for block in [block_of[DEAD], block_of[self.start]] {
+            if ids[block].is_none() {
+                ids[block] = Some(order.len());
+                order.push(block);
+            }
+        }
+        let mut i = 0;
+        while i < order.len() {
+            let state = blocks[order[i]][0];
+            for class in 0..classes {
+                let next = block_of[self.transitions[state * classes + class]];
+                if ids[next].is_none() {
+                    ids[next] = Some(order.len());
+                    order.push(next);
+                }
+            }
+            i += 1;
+        }
+
+        let mut dfa = DFA {
+            alphabet: self.alphabet.clone(),
+            start: DEAD,
+            transitions: Vec::with_capacity(order.len() * classes),
+            accepting: vec![],
+            accepting_at_end: vec![],
+        };
+        for block in &order {
+            let state = blocks[*block][0];
+            dfa.accepting.push(self.accepting[state]);
+            dfa.accepting_at_end.push(self.accepting_at_end[state]);
+            for class in 0..classes {
+                let next = block_of[self.transitions[state * classes + class]];
+                dfa.transitions.push(ids[next].unwrap());
+            }
+        }
+        dfa.start = ids[block_of[self.start]].unwrap();
+        dfa
+    }
+
+    /**
+     * Look up the DFA state for a subset, adding a new row to the table
+     * if it has not been seen before.
+     */
+    fn intern(
+        &mut self,
+        nfa: &NFA,
+        scratch: &mut Scratch,
+        subsets: &mut Vec<Subset>,
+        ids: &mut HashMap<Subset, usize>,
+        subset: Subset,
+    ) -> Result<usize, String> {
+        if subset.is_dead() {
+            return Ok(DEAD);
+        }
+        if let Some(id) = ids.get(&subset) {
+            return Ok(*id);
+        }
+        if subsets.len() >= STATE_LIMIT {
+            return Err(format!("DFA exceeds the limit of {} states", STATE_LIMIT));
+        }
+        let id = subsets.len();
+        self.accepting.push(subset.accepting(nfa));
+        self.accepting_at_end
+            .push(subset.accepting_at_end(nfa, scratch));
+        self.transitions
+            .resize(self.transitions.len() + self.alphabet.classes(), DEAD);
+        ids.insert(subset.clone(), id);
+        subsets.push(subset);
+        Ok(id)
+    }
+}
+
+/**
+ * The NFA states a DFA state stands for, sorted so equal sets compare
+ * equal. A state at the start of input is kept apart from the same set
+ * reached later, since ^ holds only for the former.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(super) struct Subset {
+    states: Vec<StateId>,
+    at_start: bool,
+}
+
+/**
+ * Space for computing epsilon closures, reused between subsets.
+ */
+#[derive(Debug)]
+pub(super) struct Scratch {
+    set: SparseSet,
+    stack: Vec<StateId>,
+}
+
+impl Scratch {
+    pub(super) fn new(nfa: &NFA) -> Scratch {
+        Scratch {
+            set: SparseSet::new(nfa.states.len()),
+            stack: vec![],
+        }
+    }
+}
+
+impl Subset {
+    /**
+     * The empty subset of the dead state.
+     */
+    pub(super) fn dead() -> Subset {
+        Subset {
+            states: vec![],
+            at_start: false,
+        }
+    }
+
+    /**
+     * The subset the NFA is in before reading any input.
+     */
+    pub(super) fn start(nfa: &NFA, scratch: &mut Scratch) -> Subset {
+        Subset::closure(nfa, scratch, &[nfa.start], true, None)
+    }
+
+    /**
+     * The subset the NFA is in after reading c from this one.
+     */
+    pub(super) fn step(&self, nfa: &NFA, scratch: &mut Scratch, c: Unit) -> Subset {
+        let mut targets = vec![];
+        for id in &self.states {
+            if let Match(label, Some(to)) = &nfa.states[*id] {
+                if label.matches(c) {
+                    targets.push(*to);
+                }
+            }
+        }
+        Subset::closure(nfa, scratch, &targets, false, Some(c))
+    }
+
+    pub(super) fn is_dead(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /**
+     * End is reachable without looking past the current position.
+     */
+    pub(super) fn accepting(&self, nfa: &NFA) -> bool {
+        self.states
+            .iter()
+            .any(|id| matches!(nfa.states[*id], End(_)))
+    }
+
+    /**
+     * End is reachable once the input has ended, which satisfies $.
+     */
+    pub(super) fn accepting_at_end(&self, nfa: &NFA, scratch: &mut Scratch) -> bool {
+        let prev = if self.at_start {
+            None
+        } else {
+            Some(Unit::Char('\0'))
+        };
+        scratch.set.clear();
+        for id in &self.states {
+            nfa.add_closure(&mut scratch.set, &mut scratch.stack, *id, prev, None);
+        }
+        scratch
+            .set
+            .iter()
+            .any(|id| matches!(nfa.states[*id], End(_)))
+    }
+
+    /**
+     * An estimate of the heap space this subset takes up.
+     */
+    pub(super) fn memory_usage(&self) -> usize {
+        self.states.len() * std::mem::size_of::<StateId>()
+    }
+
+    /**
+     * The epsilon closure of some NFA states in the middle of the input,
+     * where $ does not hold yet. Whether ^ holds depends on at_start.
+     */
+    fn closure(
+        nfa: &NFA,
+        scratch: &mut Scratch,
+        ids: &[StateId],
+        at_start: bool,
+        prev: Option<Unit>,
+    ) -> Subset {
+        // any char will do for the lookahead; only its presence matters
+        let next = Some(Unit::Char('\0'));
+        scratch.set.clear();
+        for id in ids {
+            nfa.add_closure(&mut scratch.set, &mut scratch.stack, *id, prev, next);
+        }
+        let mut states: Vec<StateId> = scratch.set.iter().cloned().collect();
+        states.sort_unstable();
+        Subset { states, at_start }
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    fn compile(pattern: &str) -> DFA {
+        DFA::new(&NFA::from(pattern).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn alphabet_classes() {
+        let alphabet = Alphabet::new(&NFA::from("a[c-e].").unwrap());
+        // [0, a) [a] (a, c) [c-e] (e, strays) [strays] (strays, MAX]
+        assert_eq!(alphabet.classes(), 7);
+        let class = |c| alphabet.class(Unit::Char(c));
+        assert_eq!(class('a'), 1);
+        assert_eq!(class('c'), class('e'));
+        assert_ne!(class('b'), class('c'));
+        assert_eq!(class('z'), class('é'));
+        assert_eq!(
+            alphabet.class(Unit::Byte(0x80)),
+            alphabet.class(Unit::Byte(0xFF))
+        );
+        assert_ne!(alphabet.class(Unit::Byte(0xE9)), class('é'));
+    }
+
+    #[test]
+    fn accepts_bytes_like_nfa() {
+        let patterns = [
+            "[\\x80-\\xff]+x",
+            "^[^\\xe9]$",
+            "caf.$",
+            "\\xe9|é",
+            "[a\\xe9]",
+        ];
+        let inputs: [&[u8]; 7] = [
+            b"\xe9\xffx",
+            b"\xe9",
+            b"\xea",
+            "é".as_bytes(),
+            b"caf\xe9",
+            b"cafe",
+            b"a",
+        ];
+        for pattern in &patterns {
+            let nfa = NFA::unanchored(pattern).unwrap();
+            let dfa = DFA::new(&nfa).unwrap();
+            for input in &inputs {
+                assert_eq!(
+                    nfa.accepts(input),
+                    dfa.accepts(input),
+                    "{} {:?}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn accepts_like_nfa() {
+        let patterns = ["ab*c", "(a|b).d", "x[0-9]{2,3}y", "colou?r", "(a*)*b"];
+        let inputs = [
+            "abbc", "ac", "bad", "x12y", "x1234y", "color", "aab", "", "z",
+        ];
+        for pattern in &patterns {
+            let nfa = NFA::from(pattern).unwrap();
+            let dfa = DFA::new(&nfa).unwrap();
+            for input in &inputs {
+                assert_eq!(
+                    nfa.accepts(input),
+                    dfa.accepts(input),
+                    "{} {}",
+                    pattern,
+                    input
+                );
+                assert_eq!(
+                    nfa.accepts_full(input),
+                    dfa.accepts_full(input),
+                    "{} {}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unanchored_search() {
+        let dfa = DFA::new(&NFA::unanchored("unc").unwrap()).unwrap();
+        assert!(dfa.accepts("bounce"));
+        assert!(dfa.accepts("munch"));
+        assert!(!dfa.accepts("under"));
+    }
+
+    #[test]
+    fn anchors() {
+        let dfa = DFA::new(&NFA::unanchored("^ab|c$").unwrap()).unwrap();
+        assert!(dfa.accepts("abx"));
+        assert!(!dfa.accepts("xab"));
+        assert!(dfa.accepts("xc"));
+        assert!(!dfa.accepts("cx"));
+
+        assert!(compile("^$").accepts(""));
+        assert!(!compile("^$").accepts("a"));
+    }
+
+    #[test]
+    fn non_ascii() {
+        let dfa = compile("caf[é-ë]");
+        assert!(dfa.accepts("café"));
+        assert!(!dfa.accepts("cafe"));
+    }
+
+    #[test]
+    fn minimize_merges_states() {
+        // (a|b)*abb has the textbook four state minimal DFA, plus dead
+        let dfa = compile("(a|b)*abb");
+        let min = dfa.minimize();
+        assert!(min.states() < dfa.states());
+        assert_eq!(min.states(), 5);
+    }
+
+    #[test]
+    fn minimize_accepts_like_dfa() {
+        let patterns = ["ab*c", "(a|b).d", "x[0-9]{2,3}y", "^colou?r$", "(a*)*b"];
+        let inputs = [
+            "abbc", "ac", "bad", "x12y", "x1234y", "color", "aab", "", "z",
+        ];
+        for pattern in &patterns {
+            let dfa = DFA::new(&NFA::unanchored(pattern).unwrap()).unwrap();
+            let min = dfa.minimize();
+            for input in &inputs {
+                assert_eq!(dfa.accepts(input), min.accepts(input));
+                assert_eq!(dfa.accepts_full(input), min.accepts_full(input));
+            }
+        }
+    }
+
+    #[test]
+    fn minimize_is_canonical() {
+        let a = compile("a*a*b").minimize();
+        let b = compile("(a|aa)*b").minimize();
+        assert_eq!(a.start, b.start);
+        assert_eq!(a.transitions, b.transitions);
+        assert_eq!(a.accepting_at_end, b.accepting_at_end);
+    }
+
+    #[test]
+    fn minimize_empty_language() {
+        // ^ cannot hold after an a, so the start state is dead
+        let min = compile("a^").minimize();
+        assert_eq!(min.states(), 1);
+        assert_eq!(min.start, DEAD);
+        assert!(!min.accepts("a"));
+    }
+
+    #[test]
+    fn state_limit() {
+        let nfa = NFA::unanchored("a.{20}").unwrap();
+        assert!(DFA::new(&nfa).is_err());
+    }
+
+    #[test]
+    fn refuses_word_boundaries() {
+        assert!(DFA::new(&NFA::unanchored("\\bid\\b").unwrap()).is_err());
+        assert!(DFA::new(&NFA::unanchored("^id$").unwrap()).is_ok());
+    }
+}
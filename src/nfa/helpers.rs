@@ -1,9 +1,12 @@
-use super::NFA;
+use super::dfa::{DEAD, DFA};
+use super::utf8::{Unit, STRAY_KEYS};
 use super::Char;
+use super::Look;
 use super::State::*;
+use super::NFA;
 
-/**
- * Helper functions for visualizing our NFA
+/*
+ * Helper functions for visualizing our NFA and DFA
  * Both at the internal representation level and in dot format
  * to generate a graphical representation.
  */
@@ -32,7 +35,15 @@ pub fn nfa_dot(nfa: &NFA) -> String {
                 "\t{0} -> {1} [label=\"ε\"]\n\t{0} -> {2} [label=\"ε\"]\n",
                 id, rhs, lhs
             ),
-            End => format!("\t{} [shape=\"doublecircle\"]\n", id),
+            Assert(look, Some(next)) => format!("\t{} -> {} [label=\"{}\"]\n", id, next, look),
+            // group n is labelled (n where it opens and n) where it closes
+            Save(slot, Some(next)) if slot % 2 == 0 => {
+                format!("\t{} -> {} [label=\"({}\"]\n", id, next, slot / 2)
+            }
+            Save(slot, Some(next)) => {
+                format!("\t{} -> {} [label=\"{})\"]\n", id, next, slot / 2)
+            }
+            End(_) => format!("\t{} [shape=\"doublecircle\"]\n", id),
             _ => String::new(),
         });
     }
@@ -41,14 +52,238 @@ pub fn nfa_dot(nfa: &NFA) -> String {
 }
 
 /**
- * Used by the DOT helper function to generate labels for each edge.
+ * Generate a DOT structured string for a DFA. The edges between two
+ * states are merged into one labelled with the chars they cover, and the
+ * dead state is left out. States that accept only at the end of a line,
+ * after a $, are drawn dashed.
+ */
+pub fn dfa_dot(dfa: &DFA) -> String {
+    let classes = dfa.alphabet.classes();
+    let mut dot = String::from("digraph dfa {\n\tnode [shape = circle];\n");
+    dot.push_str(&format!(
+        "\tstart [shape=\"none\"]\n\tstart -> {}\n",
+        dfa.start
+    ));
+    for id in 0..dfa.states() {
+        if id == DEAD {
+            continue;
+        }
+        if dfa.accepting[id] {
+            dot.push_str(&format!("\t{} [shape=\"doublecircle\"]\n", id));
+        } else if dfa.accepting_at_end[id] {
+            dot.push_str(&format!(
+                "\t{} [shape=\"doublecircle\" style=\"dashed\"]\n",
+                id
+            ));
+        }
+        let row = &dfa.transitions[id * classes..(id + 1) * classes];
+        let mut targets: Vec<usize> = row.iter().cloned().filter(|to| *to != DEAD).collect();
+        targets.sort_unstable();
+        targets.dedup();
+        for to in targets {
+            let ranges = (0..classes)
+                .filter(|class| row[*class] == to)
+                .map(|class| dfa.alphabet.range(class))
+                .collect();
+            dot.push_str(&format!(
+                "\t{} -> {} [label=\"{}\"]\n",
+                id,
+                to,
+                edge_label(ranges)
+            ));
+        }
+    }
+    dot += "}";
+    dot
+}
+
+/**
+ * The label for an edge taken on some intervals of keys: the label of
+ * its chars, then the stray bytes it is taken on unless it is taken on
+ * chars and every stray byte, as wildcards and negated classes are.
+ */
+fn edge_label(ranges: Vec<(u32, u32)>) -> String {
+    let (bytes, chars): (Vec<_>, Vec<_>) = ranges
+        .into_iter()
+        .partition(|(lo, _)| (STRAY_KEYS.0..=STRAY_KEYS.1).contains(lo));
+    let mut labels = vec![];
+    if !chars.is_empty() {
+        labels.push(chars_label(chars).to_string());
+    }
+    let every_byte =
+        bytes.iter().map(|(lo, hi)| hi - lo + 1).sum::<u32>() == STRAY_KEYS.1 - STRAY_KEYS.0 + 1;
+    if labels.is_empty() || !every_byte {
+        for (lo, hi) in bytes {
+            let mut label = Char::Byte(byte(lo)).to_string();
+            if lo != hi {
+                label = format!("{}-{}", label, Char::Byte(byte(hi)));
+            }
+            labels.push(label);
+        }
+    }
+    labels.join(",")
+}
+
+fn byte(key: u32) -> u8 {
+    match Unit::from_key(key) {
+        Some(Unit::Byte(b)) => b,
+        _ => unreachable!("key {:x} is not a stray byte", key),
+    }
+}
+
+/**
+ * The label for an edge taken on some intervals of code points: a single
+ * char, ANY, or a class, negated if that is shorter.
+ */
+fn chars_label(ranges: Vec<(u32, u32)>) -> Char {
+    let mut merged: Vec<(char, char)> = vec![];
+    for (lo, hi) in ranges {
+        // the surrogate gap holds no chars, so skip over it
+        let lo = std::char::from_u32(lo).unwrap_or('\u{e000}');
+        let hi = std::char::from_u32(hi).unwrap_or('\u{d7ff}');
+        if lo > hi {
+            continue;
+        }
+        match merged.last_mut() {
+            Some(last) if next_char(last.1) == Some(lo) => last.1 = hi,
+            _ => merged.push((lo, hi)),
+        }
+    }
+    let mut complement = vec![];
+    let mut next = Some('\0');
+    for &(lo, hi) in &merged {
+        if let Some(from) = next {
+            if from < lo {
+                complement.push((from, prev_char(lo).unwrap()));
+            }
+        }
+        next = next_char(hi);
+    }
+    if let Some(from) = next {
+        complement.push((from, std::char::MAX));
+    }
+    match (merged.as_slice(), complement.len()) {
+        (_, 0) => Char::Any,
+        (&[(lo, hi)], _) if lo == hi => Char::Literal(lo),
+        _ if complement.len() < merged.len() => Char::Class(true, complement, vec![]),
+        _ => Char::Class(false, merged, vec![]),
+    }
+}
+
+fn next_char(c: char) -> Option<char> {
+    match c {
+        '\u{d7ff}' => Some('\u{e000}'),
+        c => std::char::from_u32(c as u32 + 1),
+    }
+}
+
+fn prev_char(c: char) -> Option<char> {
+    match c {
+        '\u{e000}' => Some('\u{d7ff}'),
+        c => std::char::from_u32((c as u32).checked_sub(1)?),
+    }
+}
+
+/**
+ * Used by the DOT helper functions to generate labels for each edge.
  */
 impl std::fmt::Display for Char {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            Char::Literal(c) => write!(f, "{}", c),
+            Char::Literal(c) => write_label_char(f, *c),
             Char::Any => write!(f, "ANY"),
+            Char::Byte(b) => write!(f, "\\\\x{:02x}", b),
+            Char::Class(negated, ranges, bytes) => {
+                write!(f, "[{}", if *negated { "^" } else { "" })?;
+                for &(lo, hi) in ranges {
+                    write_class_char(f, lo)?;
+                    if lo != hi {
+                        write!(f, "-")?;
+                        write_class_char(f, hi)?;
+                    }
+                }
+                for &(lo, hi) in bytes {
+                    write!(f, "{}", Char::Byte(lo))?;
+                    if lo != hi {
+                        write!(f, "-{}", Char::Byte(hi))?;
+                    }
+                }
+                write!(f, "]")
+            }
         }
     }
 }
 
+/**
+ * Assertions are labelled with the syntax that produced them, and those
+ * from -w the way the regex crate writes them.
+ */
+impl std::fmt::Display for Look {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Look::LineStart => write!(f, "^"),
+            Look::LineEnd => write!(f, "$"),
+            Look::WordBoundary => write!(f, "\\\\b"),
+            Look::NotWordBoundary => write!(f, "\\\\B"),
+            Look::WordStart => write!(f, "\\\\<"),
+            Look::WordEnd => write!(f, "\\\\>"),
+            Look::WordStartHalf => write!(f, "\\\\b{{start-half}}"),
+            Look::WordEndHalf => write!(f, "\\\\b{{end-half}}"),
+        }
+    }
+}
+
+/**
+ * Write one character of an edge label, escaped for a DOT string.
+ */
+fn write_label_char(f: &mut std::fmt::Formatter, c: char) -> std::fmt::Result {
+    match c {
+        '"' | '\\' => write!(f, "\\{}", c),
+        // show control characters as escapes rather than raw bytes
+        c if c.is_control() => write!(f, "\\{}", c.escape_default()),
+        c => write!(f, "{}", c),
+    }
+}
+
+/**
+ * Class members additionally escape the characters that are special
+ * inside brackets.
+ */
+fn write_class_char(f: &mut std::fmt::Formatter, c: char) -> std::fmt::Result {
+    match c {
+        ']' | '-' | '^' => write!(f, "\\\\{}", c),
+        c => write_label_char(f, c),
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    fn minimized_dot(pattern: &str) -> String {
+        dfa_dot(&DFA::new(&NFA::from(pattern).unwrap()).unwrap().minimize())
+    }
+
+    #[test]
+    fn dfa_dot_merges_edges() {
+        assert_eq!(
+            minimized_dot("a(b|c)*"),
+            "digraph dfa {\n\tnode [shape = circle];\n\tstart [shape=\"none\"]\n\tstart -> 1\n\t1 -> 2 [label=\"a\"]\n\t2 [shape=\"doublecircle\"]\n\t2 -> 2 [label=\"[b-c]\"]\n}"
+        );
+    }
+
+    #[test]
+    fn dfa_dot_bytes() {
+        assert!(minimized_dot("\\xe9").contains("[label=\"\\\\xe9\"]"));
+        assert!(minimized_dot("[^x]|\\xe9").contains("[label=\"[^x]\"]"));
+        assert!(minimized_dot("a[^x]|ab|a\\xe9c")
+            .contains("[label=\"[^x],\\\\x80-\\\\xe8,\\\\xea-\\\\xff\"]"));
+    }
+
+    #[test]
+    fn dfa_dot_labels() {
+        assert!(minimized_dot("[^x]").contains("[label=\"[^x]\"]"));
+        assert!(minimized_dot(".").contains("[label=\"ANY\"]"));
+        assert!(minimized_dot("a$").contains("style=\"dashed\""));
+    }
+}
@@ -0,0 +1,365 @@
+use super::dfa::{Alphabet, Scratch, Subset, DEAD};
+use super::utf8::{Unit, Units};
+use super::NFA;
+use std::collections::HashMap;
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Lazy DFA
+ *
+ * A lazy DFA runs the subset construction on demand: a DFA state is
+ * built the first time the input leads to it and is cached for later
+ * characters and lines. Only the states the input actually visits are
+ * ever built, so patterns whose full DFA is exponential stay usable.
+ */
+
+/**
+ * A transition that has not been computed yet.
+ */
+const UNKNOWN: usize = usize::MAX;
+
+/**
+ * The cache may be cleared this many times before its hit rate is judged.
+ */
+const MIN_CLEARS: usize = 3;
+
+/**
+ * Once judged, the cache is thrashing if it builds a state for fewer than
+ * this many characters searched, and the NFA is used from then on.
+ */
+const MIN_CHARS_PER_STATE: usize = 10;
+
+/**
+ * A LazyDFA holds what never changes during a search. The states built so
+ * far live in a separate Cache, so one LazyDFA can be shared while each
+ * search thread owns a cache.
+ */
+#[derive(Debug)]
+pub struct LazyDFA {
+    nfa: NFA,
+    alphabet: Alphabet,
+    cache_limit: usize,
+    // word boundaries can't be decided by DFA states, so every search
+    // goes to the NFA
+    nfa_only: bool,
+}
+
+/**
+ * The DFA states built so far, with a row of transitions each. Rows start
+ * out UNKNOWN and are filled in as the input needs them.
+ */
+#[derive(Debug)]
+pub struct Cache {
+    subsets: Vec<Subset>,
+    ids: HashMap<Subset, usize>,
+    transitions: Vec<usize>,
+    accepting: Vec<bool>,
+    accepting_at_end: Vec<bool>,
+    start: Option<usize>,
+    scratch: Scratch,
+    memory_usage: usize,
+    clears: usize,
+    // characters searched and states built since the last clear
+    searched: usize,
+    built: usize,
+    // the cache thrashed, so searches go to the NFA
+    gave_up: bool,
+}
+
+impl LazyDFA {
+    /**
+     * Wrap an NFA in a lazy DFA whose caches may use about cache_limit
+     * bytes before they are cleared and rebuilt.
+     */
+    pub fn new(nfa: NFA, cache_limit: usize) -> LazyDFA {
+        let alphabet = Alphabet::new(&nfa);
+        let nfa_only = nfa.has_word_assertions();
+        LazyDFA {
+            nfa,
+            alphabet,
+            cache_limit,
+            nfa_only,
+        }
+    }
+
+    /**
+     * An empty cache for searching with this lazy DFA.
+     */
+    pub fn create_cache(&self) -> Cache {
+        let mut cache = Cache {
+            subsets: vec![],
+            ids: HashMap::new(),
+            transitions: vec![],
+            accepting: vec![],
+            accepting_at_end: vec![],
+            start: None,
+            scratch: Scratch::new(&self.nfa),
+            memory_usage: 0,
+            clears: 0,
+            searched: 0,
+            built: 0,
+            gave_up: false,
+        };
+        self.clear(&mut cache);
+        cache
+    }
+
+    /**
+     * Given an input string, determine if some prefix of the input is
+     * accepted, with the same meaning as `NFA::accepts`.
+     */
+    pub fn accepts<I: AsRef<[u8]> + ?Sized>(&self, cache: &mut Cache, input: &I) -> bool {
+        if cache.gave_up || self.nfa_only {
+            return self.nfa.accepts(input);
+        }
+        let classes = self.alphabet.classes();
+        let mut state = match self.start(cache) {
+            Some(state) => state,
+            None => return self.nfa.accepts(input),
+        };
+        for c in Units::new(input.as_ref()) {
+            if cache.accepting[state] {
+                return true;
+            }
+            cache.searched += 1;
+            let class = self.alphabet.class(c);
+            state = match cache.transitions[state * classes + class] {
+                UNKNOWN => match self.add_transition(cache, state, class, c) {
+                    Some(next) => next,
+                    None => return self.nfa.accepts(input),
+                },
+                next => next,
+            };
+            if state == DEAD {
+                return false;
+            }
+        }
+        cache.accepting_at_end[state]
+    }
+
+    /**
+     * The start state, built if the cache does not have it.
+     */
+    fn start(&self, cache: &mut Cache) -> Option<usize> {
+        if let Some(start) = cache.start {
+            return Some(start);
+        }
+        let subset = Subset::start(&self.nfa, &mut cache.scratch);
+        let start = self.intern(cache, subset, None)?;
+        cache.start = Some(start);
+        Some(start)
+    }
+
+    /**
+     * Build the transition out of a state on a char of some class. This
+     * may clear the cache, so callers must use the returned id only.
+     */
+    fn add_transition(
+        &self,
+        cache: &mut Cache,
+        from: usize,
+        class: usize,
+        c: Unit,
+    ) -> Option<usize> {
+        let subset = cache.subsets[from].step(&self.nfa, &mut cache.scratch, c);
+        let classes = self.alphabet.classes();
+        let mut from = from;
+        let next = self.intern(cache, subset, Some(&mut from))?;
+        cache.transitions[from * classes + class] = next;
+        Some(next)
+    }
+
+    /**
+     * Look up the state for a subset, building it if needed. When the
+     * cache is full it is cleared first, and any state the caller still
+     * needs is re-added and its id updated. None means the cache has
+     * thrashed and the caller should use the NFA instead.
+     */
+    fn intern(&self, cache: &mut Cache, subset: Subset, keep: Option<&mut usize>) -> Option<usize> {
+        if subset.is_dead() {
+            return Some(DEAD);
+        }
+        if let Some(id) = cache.ids.get(&subset) {
+            return Some(*id);
+        }
+        if cache.memory_usage + self.state_usage(&subset) > self.cache_limit {
+            if cache.clears >= MIN_CLEARS && cache.searched < MIN_CHARS_PER_STATE * cache.built {
+                cache.gave_up = true;
+                return None;
+            }
+            let kept = keep.map(|id| (cache.subsets[*id].clone(), id));
+            self.clear(cache);
+            cache.clears += 1;
+            if let Some((kept, id)) = kept {
+                *id = self.add_state(cache, kept);
+            }
+            if cache.memory_usage + self.state_usage(&subset) > self.cache_limit {
+                cache.gave_up = true;
+                return None;
+            }
+        }
+        Some(self.add_state(cache, subset))
+    }
+
+    fn add_state(&self, cache: &mut Cache, subset: Subset) -> usize {
+        let id = cache.subsets.len();
+        cache.memory_usage += self.state_usage(&subset);
+        cache.built += 1;
+        cache.accepting.push(subset.accepting(&self.nfa));
+        cache
+            .accepting_at_end
+            .push(subset.accepting_at_end(&self.nfa, &mut cache.scratch));
+        cache
+            .transitions
+            .resize(cache.transitions.len() + self.alphabet.classes(), UNKNOWN);
+        cache.ids.insert(subset.clone(), id);
+        cache.subsets.push(subset);
+        id
+    }
+
+    /**
+     * Empty the cache down to the dead state.
+     */
+    fn clear(&self, cache: &mut Cache) {
+        cache.subsets.clear();
+        cache.ids.clear();
+        cache.transitions.clear();
+        cache.accepting.clear();
+        cache.accepting_at_end.clear();
+        cache.start = None;
+        cache.memory_usage = 0;
+        cache.searched = 0;
+        cache.built = 0;
+        let dead = self.add_state(cache, Subset::dead());
+        cache.transitions[dead * self.alphabet.classes()..].fill(DEAD);
+    }
+
+    /**
+     * The bytes a state takes up: its row of transitions, two bools, and
+     * its subset both in the list of states and as a key of the map.
+     */
+    fn state_usage(&self, subset: &Subset) -> usize {
+        self.alphabet.classes() * std::mem::size_of::<usize>()
+            + 2 * std::mem::size_of::<bool>()
+            + 2 * (std::mem::size_of::<Subset>() + subset.memory_usage())
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    #[test]
+    fn accepts_like_nfa() {
+        let patterns = [
+            "ab*c",
+            "(a|b).d",
+            "x[0-9]{2,3}y",
+            "^colou?r$",
+            "(a*)*b",
+            "\\bc",
+        ];
+        let inputs = [
+            "abbc", "ac", "bad", "x12y", "x1234y", "color", "aab", "", "z",
+        ];
+        for pattern in &patterns {
+            let lazy = LazyDFA::new(NFA::unanchored(pattern).unwrap(), 1 << 20);
+            let mut cache = lazy.create_cache();
+            for input in &inputs {
+                assert_eq!(
+                    NFA::unanchored(pattern).unwrap().accepts(input),
+                    lazy.accepts(&mut cache, input),
+                    "{} {}",
+                    pattern,
+                    input
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn builds_only_visited_states() {
+        // the full DFA for this pattern has over a million states
+        let lazy = LazyDFA::new(NFA::unanchored("a.{20}").unwrap(), 1 << 20);
+        let mut cache = lazy.create_cache();
+        assert!(lazy.accepts(
+            &mut cache,
+            &format!("{}a{}", "b".repeat(50), "c".repeat(20))
+        ));
+        assert!(!lazy.accepts(&mut cache, &"b".repeat(100)));
+        assert!(!cache.gave_up);
+        assert!(cache.subsets.len() < 100);
+    }
+
+    /**
+     * A pseudorandom string of a's and b's, which visits many states of
+     * patterns like a.{n}$.
+     */
+    fn random_ab(seed: u32, len: usize) -> String {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                if (state >> 16) & 1 == 0 {
+                    'a'
+                } else {
+                    'b'
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn clears_when_full() {
+        let nfa = NFA::unanchored("a.{6}$").unwrap();
+        let lazy = LazyDFA::new(NFA::unanchored("a.{6}$").unwrap(), 4096);
+        let mut cache = lazy.create_cache();
+        for seed in 0..20 {
+            // each random line fills the cache, and each run of b's
+            // then finds the few states it needs already built
+            let input = random_ab(seed, 12);
+            assert_eq!(lazy.accepts(&mut cache, &input), nfa.accepts(&input));
+            assert!(!lazy.accepts(&mut cache, &"b".repeat(1000)));
+        }
+        assert!(cache.clears >= MIN_CLEARS);
+        assert!(!cache.gave_up);
+        assert!(cache.memory_usage <= 4096);
+    }
+
+    #[test]
+    fn falls_back_when_thrashing() {
+        let lazy = LazyDFA::new(NFA::unanchored("a.{12}$").unwrap(), 2048);
+        let mut cache = lazy.create_cache();
+        let input = random_ab(7, 5000);
+        let expected = NFA::unanchored("a.{12}$").unwrap().accepts(&input);
+        assert_eq!(lazy.accepts(&mut cache, &input), expected);
+        assert!(cache.gave_up);
+        assert!(lazy.accepts(&mut cache, "a123456789012"));
+    }
+
+    #[test]
+    fn tiny_cache() {
+        let lazy = LazyDFA::new(NFA::unanchored("abc").unwrap(), 0);
+        let mut cache = lazy.create_cache();
+        assert!(lazy.accepts(&mut cache, "xabc"));
+        assert!(cache.gave_up);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        // the automaton is shared and only the caches are per thread
+        let lazy = LazyDFA::new(NFA::unanchored("a.{6}$").unwrap(), 4096);
+        std::thread::scope(|scope| {
+            for seed in 0..4 {
+                let lazy = &lazy;
+                scope.spawn(move || {
+                    let nfa = NFA::unanchored("a.{6}$").unwrap();
+                    let mut cache = lazy.create_cache();
+                    for line in 0..50 {
+                        let input = random_ab(seed * 100 + line, 12);
+                        assert_eq!(lazy.accepts(&mut cache, &input), nfa.accepts(&input));
+                    }
+                });
+            }
+        });
+    }
+}
@@ -0,0 +1,200 @@
+use super::sparse::SparseSet;
+use super::utf8::{self, Unit, Units};
+use super::State::*;
+use super::{Match, StateId, NFA};
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Pike VM
+ *
+ * The Pike VM extends Thompson's simulation so that every thread of the
+ * NFA carries the positions at which it crossed each Save state. When a
+ * thread reaches End its saved positions are the spans of the groups it
+ * matched.
+ */
+
+/**
+ * A position in the haystack, as a byte offset and a char offset, in
+ * which a stray byte counts as a char.
+ */
+type Position = (usize, usize);
+
+/**
+ * The epsilon closure is explored depth first. Crossing a Save state
+ * changes the slots of the thread for everything reached beyond it, so
+ * the old value is restored once those states have been explored.
+ */
+enum Frame {
+    Explore(StateId),
+    Restore(usize, Option<Position>),
+}
+
+/**
+ * The threads alive at one position: a set of states, each with its own
+ * row of slots.
+ */
+struct Threads {
+    set: SparseSet,
+    slots: Vec<Option<Position>>,
+}
+
+impl Threads {
+    fn new(nfa: &NFA, width: usize) -> Threads {
+        Threads {
+            set: SparseSet::new(nfa.states.len()),
+            slots: vec![None; nfa.states.len() * width],
+        }
+    }
+}
+
+impl NFA {
+    /**
+     * Search for the leftmost-longest match beginning at a byte offset
+     * of the haystack that is char_start chars in, reporting the spans of
+     * groups 0 through groups - 1, where group 0 is the whole match.
+     *
+     * Threads are added in order of where they started, so the first to
+     * claim a state is always the leftmost. New threads are started at
+     * every position until a match is found, and the search then runs on
+     * only to see how far the matches starting no later can extend. Among
+     * threads with the same start, a group takes the span of the thread
+     * that reached a state first, preferring the left of each alternation
+     * and more iterations of each loop.
+     */
+    pub(super) fn search(
+        &self,
+        haystack: &[u8],
+        start: usize,
+        char_start: usize,
+        groups: usize,
+    ) -> Option<Vec<Option<Match>>> {
+        let width = 2 * groups;
+        let mut current = Threads::new(self, width);
+        let mut next = Threads::new(self, width);
+        let mut stack = vec![];
+        let mut slots = vec![None; width];
+        let mut best: Option<Vec<Option<Position>>> = None;
+
+        let mut prev = utf8::decode_last(&haystack[..start]);
+        let mut chars = Units::new(&haystack[start..]).peekable();
+        let mut position = (start, char_start);
+        loop {
+            let lookahead = chars.peek().cloned();
+            if best.is_none() {
+                slots.iter_mut().for_each(|slot| *slot = None);
+                slots[0] = Some(position);
+                let seed = (self.body, position, prev, lookahead);
+                self.add_thread(&mut current, &mut stack, &mut slots, seed);
+            }
+            for id in current.set.iter() {
+                if let End(_) = self.states[*id] {
+                    let thread = &current.slots[id * width..(id + 1) * width];
+                    let better = match &best {
+                        None => true,
+                        Some(best) => {
+                            thread[0] < best[0]
+                                || (thread[0] == best[0] && best[1] < Some(position))
+                        }
+                    };
+                    if better {
+                        let mut found = thread.to_vec();
+                        found[1] = Some(position);
+                        best = Some(found);
+                    }
+                }
+            }
+            if current.set.is_empty() && best.is_some() {
+                break;
+            }
+            let ch = match chars.next() {
+                Some(ch) => ch,
+                None => break,
+            };
+            let lookahead = chars.peek().cloned();
+            let after = (position.0 + ch.len(), position.1 + 1);
+            next.set.clear();
+            for id in current.set.iter() {
+                let thread = &current.slots[id * width..(id + 1) * width];
+                if let Some(best) = &best {
+                    if thread[0] > best[0] {
+                        continue;
+                    }
+                }
+                if let Match(c, Some(to)) = &self.states[*id] {
+                    if c.matches(ch) {
+                        slots.copy_from_slice(thread);
+                        let step = (*to, after, Some(ch), lookahead);
+                        self.add_thread(&mut next, &mut stack, &mut slots, step);
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            prev = Some(ch);
+            position = after;
+        }
+
+        let best = best?;
+        Some(
+            (0..groups)
+                .map(|group| match (best[2 * group], best[2 * group + 1]) {
+                    (Some(from), Some(to)) => Some(Match {
+                        start: from.0,
+                        end: to.0,
+                        char_start: from.1,
+                        char_end: to.1,
+                    }),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+
+    /**
+     * Add the thread at a state, and the threads reachable from it by
+     * epsilon transitions, to a set. The state comes with the position it
+     * is reached at and the chars on either side for deciding assertions.
+     * States already in the set belong to a thread of higher priority and
+     * are left alone.
+     */
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        stack: &mut Vec<Frame>,
+        slots: &mut [Option<Position>],
+        (id, at, prev, next): (StateId, Position, Option<Unit>, Option<Unit>),
+    ) {
+        let width = slots.len();
+        stack.push(Frame::Explore(id));
+        while let Some(frame) = stack.pop() {
+            let id = match frame {
+                Frame::Explore(id) => id,
+                Frame::Restore(slot, value) => {
+                    slots[slot] = value;
+                    continue;
+                }
+            };
+            if !threads.set.insert(id) {
+                continue;
+            }
+            match &self.states[id] {
+                Start(Some(to)) => stack.push(Frame::Explore(*to)),
+                Split(Some(lhs), Some(rhs)) => {
+                    stack.push(Frame::Explore(*rhs));
+                    stack.push(Frame::Explore(*lhs));
+                }
+                Assert(look, Some(to)) if look.holds(prev, next) => stack.push(Frame::Explore(*to)),
+                Save(slot, Some(to)) => {
+                    // groups beyond those asked for are not tracked
+                    if *slot < width {
+                        stack.push(Frame::Restore(*slot, slots[*slot]));
+                        slots[*slot] = Some(at);
+                    }
+                    stack.push(Frame::Explore(*to));
+                }
+                Match(_, _) | End(_) => {
+                    threads.slots[id * width..(id + 1) * width].copy_from_slice(slots);
+                }
+                _ => {}
+            }
+        }
+    }
+}
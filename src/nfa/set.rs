@@ -0,0 +1,174 @@
+use super::sparse::SparseSet;
+use super::utf8::Units;
+use super::State::*;
+use super::{Flags, NFA};
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Pattern Sets
+ *
+ * Many patterns are compiled into one NFA whose Start leads to all of
+ * them, and each pattern ends in an End state tagged with its index. A
+ * single simulation over the input then sees every pattern that accepts
+ * it, by the tags of the End states it reaches.
+ */
+
+/**
+ * A set of patterns matched against an input together.
+ */
+#[derive(Debug)]
+pub struct NFASet {
+    nfa: NFA,
+    len: usize,
+}
+
+impl NFASet {
+    /**
+     * Compile a set of patterns. Each is numbered by its index in the
+     * slice, and errors name the pattern they are in.
+     */
+    pub fn new<S: AsRef<str>>(patterns: &[S], flags: Flags) -> Result<NFASet, String> {
+        Ok(NFASet::from_nfa(
+            NFA::with_patterns(patterns, flags)?,
+            patterns.len(),
+        ))
+    }
+
+    /**
+     * The set of the len patterns an NFA was built from by
+     * `NFA::with_patterns`, without compiling them again.
+     */
+    pub fn from_nfa(nfa: NFA, len: usize) -> NFASet {
+        NFASet { nfa, len }
+    }
+
+    /**
+     * The number of patterns in the set.
+     */
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /**
+     * The NFA for all the patterns at once, which matches wherever any
+     * of them does.
+     */
+    pub fn nfa(&self) -> &NFA {
+        &self.nfa
+    }
+
+    /**
+     * The indices, in increasing order, of the patterns that accept the
+     * input in the sense of `NFA::accepts`. Unlike `accepts`, the
+     * simulation goes on past the first acceptance until every pattern
+     * has accepted or none still can.
+     */
+    pub fn matches<I: AsRef<[u8]> + ?Sized>(&self, input: &I) -> Vec<usize> {
+        let nfa = &self.nfa;
+        let mut accepted = vec![false; self.len];
+        let mut remaining = self.len;
+        let mut current = SparseSet::new(nfa.states.len());
+        let mut next = SparseSet::new(nfa.states.len());
+        let mut stack = Vec::new();
+        let mut chars = Units::new(input.as_ref()).peekable();
+        nfa.add_closure(
+            &mut current,
+            &mut stack,
+            nfa.start,
+            None,
+            chars.peek().cloned(),
+        );
+        loop {
+            for id in current.iter() {
+                if let End(tag) = nfa.states[*id] {
+                    if !accepted[tag] {
+                        accepted[tag] = true;
+                        remaining -= 1;
+                    }
+                }
+            }
+            if current.is_empty() || remaining == 0 {
+                break;
+            }
+            let ch = match chars.next() {
+                Some(ch) => ch,
+                None => break,
+            };
+            next.clear();
+            let lookahead = chars.peek().cloned();
+            for id in current.iter() {
+                if let Match(c, Some(to)) = &nfa.states[*id] {
+                    if c.matches(ch) {
+                        nfa.add_closure(&mut next, &mut stack, *to, Some(ch), lookahead);
+                    }
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+        (0..self.len).filter(|tag| accepted[*tag]).collect()
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    fn set(patterns: &[&str]) -> NFASet {
+        let flags = Flags {
+            unanchored: true,
+            ..Flags::default()
+        };
+        NFASet::new(patterns, flags).unwrap()
+    }
+
+    #[test]
+    fn reports_every_match() {
+        let set = set(&["error", "disk", "^warn", "[0-9]+$", "x"]);
+        assert_eq!(set.len(), 5);
+        assert_eq!(set.matches("error: disk full"), vec![0, 1]);
+        assert_eq!(set.matches("warn: error 42"), vec![0, 2, 3]);
+        assert_eq!(set.matches("all good"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn overlapping_patterns() {
+        // the same text is matched by several patterns at once
+        let set = set(&["ab", "a.", "(a|b)+", "abc"]);
+        assert_eq!(set.matches("ab"), vec![0, 1, 2]);
+        assert_eq!(set.matches("zabc"), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn agrees_with_each_nfa() {
+        let patterns = ["a*b", "^b", "c$", "(ab|ba){2}", "[^ab]"];
+        let set = set(&patterns);
+        for input in &["", "b", "ab", "abba", "cab", "bac", "xyz", "aaa"] {
+            let expected: Vec<usize> = (0..patterns.len())
+                .filter(|i| NFA::unanchored(patterns[*i]).unwrap().accepts(input))
+                .collect();
+            assert_eq!(set.matches(input), expected, "{}", input);
+        }
+    }
+
+    #[test]
+    fn anchored() {
+        let set = NFASet::new(&["a", "ab"], Flags::default()).unwrap();
+        assert_eq!(set.matches("abc"), vec![0, 1]);
+        assert_eq!(set.matches("ba"), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn shares_one_nfa() {
+        let set = set(&["cat", "dog"]);
+        assert!(set.nfa().accepts("hotdog"));
+        assert_eq!(set.nfa().find("a dog").unwrap().start, 2);
+        assert!(!set.is_empty());
+        assert!(NFASet::new(&[] as &[&str], Flags::default())
+            .unwrap()
+            .matches("x")
+            .is_empty());
+    }
+}
@@ -0,0 +1,79 @@
+/**
+ * A set of StateIds drawn from 0..capacity, after Briggs and Torczon.
+ * Insertion, membership and clearing are all constant time, and
+ * iteration follows insertion order, which lets the simulation reuse
+ * the same two sets for every step without rehashing or reallocating.
+ */
+#[derive(Debug)]
+pub struct SparseSet {
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl SparseSet {
+    pub fn new(capacity: usize) -> SparseSet {
+        SparseSet {
+            dense: Vec::with_capacity(capacity),
+            sparse: vec![0; capacity],
+        }
+    }
+
+    /**
+     * Add an id to the set, returning false if it was already there.
+     */
+    pub fn insert(&mut self, id: usize) -> bool {
+        if self.contains(id) {
+            return false;
+        }
+        self.sparse[id] = self.dense.len();
+        self.dense.push(id);
+        true
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        let index = self.sparse[id];
+        index < self.dense.len() && self.dense[index] == id
+    }
+
+    pub fn clear(&mut self) {
+        self.dense.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.dense.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dense.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, usize> {
+        self.dense.iter()
+    }
+}
+
+#[cfg(test)]
+mod sparse_set {
+    use super::*;
+
+    #[test]
+    fn insert_and_contains() {
+        let mut set = SparseSet::new(8);
+        assert!(set.insert(5));
+        assert!(set.insert(2));
+        assert!(!set.insert(5));
+        assert!(set.contains(2));
+        assert!(!set.contains(3));
+        assert_eq!(set.iter().cloned().collect::<Vec<_>>(), vec![5, 2]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut set = SparseSet::new(4);
+        set.insert(1);
+        set.clear();
+        assert!(set.is_empty());
+        assert!(!set.contains(1));
+        assert!(set.insert(1));
+    }
+}
@@ -0,0 +1,193 @@
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - UTF-8 Input
+ *
+ * Input is searched as bytes but matched a char at a time. Each valid
+ * UTF-8 sequence is decoded to its char, and each byte that is not part
+ * of one, such as a Latin-1 é in an otherwise UTF-8 file, stands for
+ * itself. Searching never fails on bad input; stray bytes simply match
+ * nothing but wildcards, classes and \xNN literals.
+ */
+
+/**
+ * One step of input: a char, or a byte that is not part of any.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Unit {
+    Char(char),
+    Byte(u8),
+}
+
+/**
+ * Stray bytes are keyed in the surrogate gap, where no char can be, as
+ * Python's surrogateescape does, so that one range of keys covers them
+ * and keys keep the order of chars.
+ */
+const BYTE_KEYS: u32 = 0xDC00;
+
+/**
+ * The keys of the stray bytes, which are never ASCII.
+ */
+pub(super) const STRAY_KEYS: (u32, u32) = (BYTE_KEYS + 0x80, BYTE_KEYS + 0xFF);
+
+impl Unit {
+    /**
+     * The number of bytes the unit takes up in the input.
+     */
+    pub(super) fn len(self) -> usize {
+        match self {
+            Unit::Char(c) => c.len_utf8(),
+            Unit::Byte(_) => 1,
+        }
+    }
+
+    /**
+     * A code point standing for the unit, for indexing by ranges.
+     */
+    pub(super) fn key(self) -> u32 {
+        match self {
+            Unit::Char(c) => c as u32,
+            Unit::Byte(b) => BYTE_KEYS + b as u32,
+        }
+    }
+
+    /**
+     * The unit a key stands for, if there can be one.
+     */
+    pub(super) fn from_key(key: u32) -> Option<Unit> {
+        if (STRAY_KEYS.0..=STRAY_KEYS.1).contains(&key) {
+            return Some(Unit::Byte((key - BYTE_KEYS) as u8));
+        }
+        std::char::from_u32(key).map(Unit::Char)
+    }
+
+    /**
+     * The unit as it appears in the input.
+     */
+    pub(super) fn encode(self, bytes: &mut Vec<u8>) {
+        match self {
+            Unit::Char(c) => bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+            Unit::Byte(b) => bytes.push(b),
+        }
+    }
+}
+
+/**
+ * The first unit of some input.
+ */
+pub(super) fn decode(bytes: &[u8]) -> Option<Unit> {
+    let first = *bytes.first()?;
+    let len = match first {
+        0x00..=0x7F => return Some(Unit::Char(first as char)),
+        0xC2..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF4 => 4,
+        _ => return Some(Unit::Byte(first)),
+    };
+    match bytes.get(..len).map(std::str::from_utf8) {
+        Some(Ok(decoded)) => decoded.chars().next().map(Unit::Char),
+        _ => Some(Unit::Byte(first)),
+    }
+}
+
+/**
+ * The last unit of some input. A lead byte is never a continuation byte,
+ * so the longest valid sequence at the end is the one decoding forwards
+ * would have found.
+ */
+pub(super) fn decode_last(bytes: &[u8]) -> Option<Unit> {
+    let last = *bytes.last()?;
+    for len in (2..=bytes.len().min(4)).rev() {
+        match decode(&bytes[bytes.len() - len..]) {
+            Some(Unit::Char(c)) if c.len_utf8() == len => return Some(Unit::Char(c)),
+            _ => {}
+        }
+    }
+    decode(&[last])
+}
+
+/**
+ * An iterator over the units of some input.
+ */
+#[derive(Debug, Clone)]
+pub(super) struct Units<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> Units<'b> {
+    pub(super) fn new(bytes: &'b [u8]) -> Units<'b> {
+        Units { bytes }
+    }
+}
+
+impl<'b> Iterator for Units<'b> {
+    type Item = Unit;
+
+    fn next(&mut self) -> Option<Unit> {
+        let unit = decode(self.bytes)?;
+        self.bytes = &self.bytes[unit.len()..];
+        Some(unit)
+    }
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    fn units(bytes: &[u8]) -> Vec<Unit> {
+        Units::new(bytes).collect()
+    }
+
+    #[test]
+    fn decodes_valid_utf8() {
+        assert_eq!(
+            units("aé€😀".as_bytes()),
+            vec![
+                Unit::Char('a'),
+                Unit::Char('é'),
+                Unit::Char('€'),
+                Unit::Char('😀')
+            ]
+        );
+    }
+
+    #[test]
+    fn stray_bytes() {
+        // Latin-1 é, a lone continuation byte, a truncated €, an overlong /
+        assert_eq!(
+            units(b"\xe9a\x80\xe2\x82b\xc0\xaf"),
+            vec![
+                Unit::Byte(0xE9),
+                Unit::Char('a'),
+                Unit::Byte(0x80),
+                Unit::Byte(0xE2),
+                Unit::Byte(0x82),
+                Unit::Char('b'),
+                Unit::Byte(0xC0),
+                Unit::Byte(0xAF)
+            ]
+        );
+        // an encoded surrogate is not valid either
+        assert_eq!(units(b"\xed\xa0\x80").len(), 3);
+    }
+
+    #[test]
+    fn decodes_backwards() {
+        assert_eq!(decode_last(b""), None);
+        assert_eq!(decode_last(b"ab"), Some(Unit::Char('b')));
+        assert_eq!(decode_last("x€".as_bytes()), Some(Unit::Char('€')));
+        assert_eq!(decode_last(b"x\xe2\x82"), Some(Unit::Byte(0x82)));
+        assert_eq!(decode_last(b"\xe9"), Some(Unit::Byte(0xE9)));
+    }
+
+    #[test]
+    fn keys() {
+        for unit in &[Unit::Char('a'), Unit::Char('\u{10FFFF}'), Unit::Byte(0xE9)] {
+            assert_eq!(Unit::from_key(unit.key()), Some(*unit));
+        }
+        assert_eq!(Unit::from_key(0xD800), None);
+        let mut bytes = vec![];
+        Unit::Char('é').encode(&mut bytes);
+        Unit::Byte(0xE9).encode(&mut bytes);
+        assert_eq!(bytes, b"\xc3\xa9\xe9");
+    }
+}
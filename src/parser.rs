@@ -1,5 +1,5 @@
+use super::casefold::fold_ranges;
 use super::tokenizer::{Token, Tokenizer};
-use std::iter::Peekable;
 
 /**
  * thegrep - Tar Heel Extended Regular Expressions - Parser
@@ -19,8 +19,20 @@ pub enum AST {
     Catenation(Box<AST>, Box<AST>),
     Closure(Box<AST>),
     OneOrMore(Box<AST>),
+    Optional(Box<AST>),
+    Repeat(Box<AST>, u32, Option<u32>),
+    Group(usize, Option<String>, Box<AST>),
+    Empty,
     Char(char),
+    Byte(u8),
     AnyChar,
+    Class(bool, Vec<(char, char)>, Vec<(u8, u8)>),
+    LineStart,
+    LineEnd,
+    WordBoundary,
+    NotWordBoundary,
+    WordStart,
+    WordEnd,
 }
 
 /* Helper factory functions for building Exprs */
@@ -40,33 +52,153 @@ pub fn create_one_or_more(expression: AST) -> AST {
     AST::OneOrMore(Box::new(expression))
 }
 
+pub fn create_optional(expression: AST) -> AST {
+    AST::Optional(Box::new(expression))
+}
+
+pub fn create_repeat(expression: AST, min: u32, max: Option<u32>) -> AST {
+    AST::Repeat(Box::new(expression), min, max)
+}
+
+pub fn create_group(index: usize, expression: AST) -> AST {
+    AST::Group(index, None, Box::new(expression))
+}
+
+pub fn create_named_group(index: usize, name: &str, expression: AST) -> AST {
+    AST::Group(index, Some(name.to_string()), Box::new(expression))
+}
+
+pub fn create_empty() -> AST {
+    AST::Empty
+}
+
 pub fn create_char(value: char) -> AST {
     AST::Char(value)
 }
 
+pub fn create_byte(value: u8) -> AST {
+    AST::Byte(value)
+}
+
 pub fn create_any_char() -> AST {
     AST::AnyChar
 }
+
+pub fn create_line_start() -> AST {
+    AST::LineStart
+}
+
+pub fn create_line_end() -> AST {
+    AST::LineEnd
+}
+
+pub fn create_word_boundary() -> AST {
+    AST::WordBoundary
+}
+
+pub fn create_not_word_boundary() -> AST {
+    AST::NotWordBoundary
+}
+
+pub fn create_word_start() -> AST {
+    AST::WordStart
+}
+
+pub fn create_word_end() -> AST {
+    AST::WordEnd
+}
+
+pub fn create_class(negated: bool, ranges: Vec<(char, char)>) -> AST {
+    AST::Class(negated, ranges, vec![])
+}
+
+pub fn create_class_with_bytes(
+    negated: bool,
+    ranges: Vec<(char, char)>,
+    bytes: Vec<(u8, u8)>,
+) -> AST {
+    AST::Class(negated, ranges, bytes)
+}
 /* == End Syntax Tree Elements == */
 
+/* Bounded repetition is expanded into copies of its operand, so cap it */
+const REPEAT_LIMIT: u32 = 1000;
+
+/* Nested repeats multiply, so also cap the size of the expanded pattern */
+const EXPANSION_LIMIT: u64 = 100_000;
+
+/**
+ * The number of nodes a syntax tree has once each repeat is expanded into
+ * the copies of its operand the NFA is built from.
+ */
+fn expanded_size(ast: &AST) -> u64 {
+    match ast {
+        AST::Alternation(lhs, rhs) | AST::Catenation(lhs, rhs) => 1u64
+            .saturating_add(expanded_size(lhs))
+            .saturating_add(expanded_size(rhs)),
+        AST::Closure(expr) | AST::OneOrMore(expr) | AST::Optional(expr) => {
+            1u64.saturating_add(expanded_size(expr))
+        }
+        AST::Group(_, _, expr) => 1u64.saturating_add(expanded_size(expr)),
+        // an unbounded repeat is min copies and then a closure over one more
+        AST::Repeat(expr, min, max) => {
+            let copies = u64::from(max.unwrap_or(min + 1).max(1));
+            1u64.saturating_add(expanded_size(expr).saturating_mul(copies))
+        }
+        _ => 1,
+    }
+}
+
 pub struct Parser<'tokens> {
-    tokens: Peekable<Tokenizer<'tokens>>,
+    tokens: Tokenizer<'tokens>,
+    // groups are numbered from 1 in the order of their left parens
+    groups: usize,
+    names: Vec<String>,
+    // set by -i or (?i); literals and classes then match either case
+    case_insensitive: bool,
 }
 
 impl<'tokens> Parser<'tokens> {
     pub fn parse(tokenizer: Tokenizer<'tokens>) -> Result<AST, String> {
+        Parser::parse_with(tokenizer, false)
+    }
+
+    /**
+     * Parse a pattern that starts out case-insensitive, as if it began
+     * with (?i) when case_insensitive is true. Errors give the char
+     * offset of the token the parser stopped at.
+     */
+    pub fn parse_with(
+        tokenizer: Tokenizer<'tokens>,
+        case_insensitive: bool,
+    ) -> Result<AST, String> {
+        Parser::parse_after(tokenizer, case_insensitive, 0)
+    }
+
+    /**
+     * Parse one of several patterns compiled together, numbering its
+     * groups after the first groups taken by the patterns before it.
+     */
+    pub fn parse_after(
+        tokenizer: Tokenizer<'tokens>,
+        case_insensitive: bool,
+        groups: usize,
+    ) -> Result<AST, String> {
         let mut parser = Parser {
-            tokens: tokenizer.peekable(),
+            tokens: tokenizer,
+            groups,
+            names: vec![],
+            case_insensitive,
         };
-        let p = parser.reg_expr();
-        if parser.tokens.peek() != None {
-            Err(format!(
-                "Expected end of input, found {:?}",
-                parser.tokens.next().unwrap()
-            ))
-        } else {
-            Ok(p?)
+        // an empty pattern matches the empty string, and so every line
+        if parser.tokens.peek().is_none() {
+            return Ok(create_empty());
         }
+        let p = parser.reg_expr().and_then(|p| match parser.tokens.peek() {
+            Some(token) => Err(format!("Expected end of input, found {:?}", token)),
+            None => Ok(p),
+        });
+        p.map_err(|message| format!("{} at position {}", message, parser.tokens.position()))
     }
 }
 
@@ -74,7 +206,7 @@ impl<'tokens> Parser<'tokens> {
 mod public_api {
     use super::*;
 
-    mod createAST {
+    mod create_ast {
         use super::*;
         #[test]
         fn parse_char() {
@@ -116,11 +248,238 @@ mod public_api {
         }
 
         #[test]
-        fn parse_oneOrMore() {
+        fn parse_one_or_more() {
             let parsed = Parser::parse(Tokenizer::new("a+")).unwrap();
             assert_eq!(parsed, create_one_or_more(create_char('a')));
         }
 
+        #[test]
+        fn parse_escaped() {
+            let parsed = Parser::parse(Tokenizer::new("a\\.\\*")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_char('a'),
+                    create_catenation(create_char('.'), create_char('*'))
+                )
+            );
+        }
+
+        #[test]
+        fn parse_bad_escape() {
+            assert_eq!(
+                Parser::parse(Tokenizer::new("ab\\")),
+                Err(String::from("Trailing backslash at position 2"))
+            );
+        }
+
+        #[test]
+        fn parse_error_positions() {
+            assert_eq!(
+                Parser::parse(Tokenizer::new("(ab")),
+                Err(String::from("Unexpected end of input at position 3"))
+            );
+            assert_eq!(
+                Parser::parse(Tokenizer::new("ab)c")),
+                Err(String::from(
+                    "Expected end of input, found RParen at position 2"
+                ))
+            );
+            assert_eq!(
+                Parser::parse(Tokenizer::new("a|(?z)")),
+                Err(String::from("Unknown group flag 'z' at position 2"))
+            );
+        }
+
+        #[test]
+        fn parse_class() {
+            let parsed = Parser::parse(Tokenizer::new("[^a-c_]*x")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_closure(create_class(true, vec![('a', 'c'), ('_', '_')])),
+                    create_char('x')
+                )
+            );
+        }
+
+        #[test]
+        fn parse_quantifiers() {
+            let parsed = Parser::parse(Tokenizer::new("ab?[0-9]{4}")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_char('a'),
+                    create_catenation(
+                        create_optional(create_char('b')),
+                        create_repeat(create_class(false, vec![('0', '9')]), 4, Some(4))
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn parse_anchors() {
+            let parsed = Parser::parse(Tokenizer::new("^a|b$")).unwrap();
+            assert_eq!(
+                parsed,
+                create_alternation(
+                    create_catenation(create_line_start(), create_char('a')),
+                    create_catenation(create_char('b'), create_line_end())
+                )
+            );
+        }
+
+        #[test]
+        fn parse_word_assertions() {
+            let parsed = Parser::parse(Tokenizer::new("\\<a\\b\\B\\>")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_word_start(),
+                    create_catenation(
+                        create_char('a'),
+                        create_catenation(
+                            create_word_boundary(),
+                            create_catenation(create_not_word_boundary(), create_word_end())
+                        )
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn parse_bytes() {
+            let parsed = Parser::parse(Tokenizer::new("\\xe9\\x41")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(create_byte(0xE9), create_char('A'))
+            );
+        }
+
+        #[test]
+        fn parse_groups() {
+            let parsed = Parser::parse(Tokenizer::new("((a)|b)(c)*")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_group(
+                        1,
+                        create_alternation(create_group(2, create_char('a')), create_char('b'))
+                    ),
+                    create_closure(create_group(3, create_char('c')))
+                )
+            );
+        }
+
+        #[test]
+        fn parse_groups_after() {
+            assert_eq!(
+                Parser::parse_after(Tokenizer::new("(a)(b)"), false, 2),
+                Ok(create_catenation(
+                    create_group(3, create_char('a')),
+                    create_group(4, create_char('b'))
+                ))
+            );
+        }
+
+        #[test]
+        fn parse_named_groups() {
+            let parsed = Parser::parse(Tokenizer::new("(?P<key>a)(b)(?<value>c)")).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_named_group(1, "key", create_char('a')),
+                    create_catenation(
+                        create_group(2, create_char('b')),
+                        create_named_group(3, "value", create_char('c'))
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn parse_duplicate_group_name() {
+            assert_eq!(
+                Parser::parse(Tokenizer::new("(?P<x>a)|(?P<x>b)")),
+                Err(String::from("Duplicate group name x at position 9"))
+            );
+        }
+
+        #[test]
+        fn parse_case_insensitive() {
+            let parsed = Parser::parse_with(Tokenizer::new("a1[^x]"), true).unwrap();
+            assert_eq!(
+                parsed,
+                create_catenation(
+                    create_class(false, vec![('A', 'A'), ('a', 'a')]),
+                    create_catenation(
+                        create_char('1'),
+                        create_class(true, vec![('X', 'X'), ('x', 'x')])
+                    )
+                )
+            );
+        }
+
+        #[test]
+        fn parse_inline_flags() {
+            let folded = || create_class(false, vec![('B', 'B'), ('b', 'b')]);
+            assert_eq!(
+                Parser::parse(Tokenizer::new("a(?i)b")).unwrap(),
+                create_catenation(create_char('a'), folded())
+            );
+            // flags end with the group they are set in
+            assert_eq!(
+                Parser::parse(Tokenizer::new("((?i)b)b")).unwrap(),
+                create_catenation(create_group(1, folded()), create_char('b'))
+            );
+            assert_eq!(
+                Parser::parse(Tokenizer::new("(?i:b)(?-i:b)")).unwrap(),
+                create_catenation(folded(), create_char('b'))
+            );
+            assert_eq!(
+                Parser::parse_with(Tokenizer::new("(?-i)b"), true).unwrap(),
+                create_char('b')
+            );
+        }
+
+        #[test]
+        fn parse_class_bytes() {
+            assert_eq!(
+                Parser::parse_with(Tokenizer::new("[^a\\x80-\\xff]"), true).unwrap(),
+                create_class_with_bytes(true, vec![('A', 'A'), ('a', 'a')], vec![(0x80, 0xFF)])
+            );
+        }
+
+        #[test]
+        fn parse_empty() {
+            assert_eq!(Parser::parse(Tokenizer::new("")), Ok(create_empty()));
+            assert_eq!(
+                Parser::parse(Tokenizer::new("()")),
+                Err(String::from("Unexpected atom encountered at position 1"))
+            );
+        }
+
+        #[test]
+        fn parse_trailing_flags() {
+            let parse = |pattern| Parser::parse(Tokenizer::new(pattern));
+            assert_eq!(parse("(?i)"), Ok(create_empty()));
+            assert_eq!(parse("a(?i)"), Ok(create_char('a')));
+            assert_eq!(parse("(a(?i))"), Ok(create_group(1, create_char('a'))));
+            // the flags still hold in the alternatives after them
+            assert_eq!(
+                parse("(?i)|b"),
+                Ok(create_alternation(
+                    create_empty(),
+                    create_class(false, vec![('B', 'B'), ('b', 'b')])
+                ))
+            );
+            assert_eq!(
+                parse("|b"),
+                Err(String::from("Unexpected atom encountered at position 0"))
+            );
+        }
+
         #[test]
         fn parse_all() {
             let parsed = Parser::parse(Tokenizer::new("ab|c+d*(e.)")).unwrap();
@@ -132,7 +491,7 @@ mod public_api {
                         create_one_or_more(create_char('c')),
                         create_catenation(
                             create_closure(create_char('d')),
-                            create_catenation(create_char('e'), create_any_char())
+                            create_group(1, create_catenation(create_char('e'), create_any_char()))
                         )
                     )
                 )
@@ -156,80 +515,124 @@ impl<'tokens> Parser<'tokens> {
                 Token::UnionBar => {
                     self.tokens.next();
                     let rhs = self.reg_expr()?;
-                    return Ok(create_alternation(l, rhs));
+                    Ok(create_alternation(l, rhs))
                 }
-                Token::RParen => return Ok(l),
-                _ => Err(format!("Unexpected input")),
+                Token::RParen => Ok(l),
+                _ => Err("Unexpected input".to_string()),
             }
         } else {
-            return Ok(l);
+            Ok(l)
         }
     }
 
-    // Catenation -> Closure Catenation?
+    // Catenation -> SetFlags* (Closure Catenation? | Empty)
     fn catenation(&mut self) -> Result<AST, String> {
+        let mut flags = false;
+        while let Some(Token::SetFlags(case_insensitive)) = self.tokens.peek() {
+            self.case_insensitive = *case_insensitive;
+            self.tokens.next();
+            flags = true;
+        }
+        // flags may end a pattern, an alternative or a group, as in a(?i)
+        match self.tokens.peek() {
+            None | Some(Token::UnionBar) | Some(Token::RParen) if flags => {
+                return Ok(create_empty())
+            }
+            _ => {}
+        }
         let c = self.closure()?;
         if let Some(next_token) = self.tokens.peek() {
             match next_token {
                 // checks for unionbar to return to reg_expr method and
                 // rparen for atom method
-                Token::UnionBar => return Ok(c),
-                Token::RParen => return Ok(c),
-                _ => {
-                    let rhs = self.catenation()?;
-                    return Ok(create_catenation(c, rhs));
-                }
+                Token::UnionBar => Ok(c),
+                Token::RParen => Ok(c),
+                _ => match self.catenation()? {
+                    AST::Empty => Ok(c),
+                    rhs => Ok(create_catenation(c, rhs)),
+                },
             }
         } else {
-            return Ok(c);
+            Ok(c)
         }
     }
 
-    // Closure  -> Atom KleeneStar/KleenePlus?
+    // Closure  -> Atom (KleeneStar | KleenePlus | QuestionMark | Repeat)*
     fn closure(&mut self) -> Result<AST, String> {
-        let expr = self.atom()?;
-        if let Some(kleene) = self.peek_token() {
-            match kleene {
-                '*' => {
-                    // if next token is a kleene star, create a closure AST
-                    let closure = create_closure(expr);
-                    self.take_token()?;
-                    return Ok(closure);
-                }
-                '+' => {
-                    // if next token is a kleene star, create a closure AST
-                    let one_more = create_one_or_more(expr);
-                    self.take_token()?;
-                    return Ok(one_more);
+        let mut expr = self.atom()?;
+        // each postfix operator wraps everything to its left, so a{2}? is (a{2})?
+        loop {
+            expr = match self.tokens.peek() {
+                Some(Token::KleeneStar) => create_closure(expr),
+                Some(Token::KleenePlus) => create_one_or_more(expr),
+                Some(Token::QuestionMark) => create_optional(expr),
+                Some(Token::Repeat(min, max)) => {
+                    let (min, max) = (*min, *max);
+                    Parser::check_repeat(min, max)?;
+                    let repeat = create_repeat(expr, min, max);
+                    if expanded_size(&repeat) > EXPANSION_LIMIT {
+                        return Err(format!(
+                            "Nested repetition expands past the limit of {} nodes",
+                            EXPANSION_LIMIT
+                        ));
+                    }
+                    repeat
                 }
                 _ => return Ok(expr),
-            }
-        } else {
-            return Ok(expr);
+            };
+            self.tokens.next();
         }
     }
 
-    // Atom     -> LParen RegExpr Rparen | AnyChar | Char
+    // Atom     -> (LParen | NamedLParen | FlagsLParen) RegExpr Rparen | AnyChar
+    //           | Char | Byte | Class | LineStart | LineEnd | WordBoundary
+    //           | NotWordBoundary | WordStart | WordEnd
 
     fn atom(&mut self) -> Result<AST, String> {
         let t: Token = self.take_next_token()?;
         match t {
-            Token::AnyChar => return Ok(create_any_char()),
-            Token::Char(value) => return Ok(create_char(value)),
-            Token::LParen => {
-                let expr = self.reg_expr();
-                match expr {
-                    Err(message) => return Err(message),
-                    Ok(e) => {
-                        let rparen = self.consume_token(Token::RParen)?;
-                        match rparen {
-                            Token::RParen => return Ok(e),
-                            _ => return Err(format!("Missing right parenthesis")),
-                        }
-                    }
+            Token::AnyChar => Ok(create_any_char()),
+            Token::Char(value) if self.case_insensitive => {
+                match fold_ranges(&[(value, value)]).as_slice() {
+                    [_] => Ok(create_char(value)),
+                    ranges => Ok(create_class(false, ranges.to_vec())),
+                }
+            }
+            Token::Char(value) => Ok(create_char(value)),
+            Token::Byte(value) => Ok(create_byte(value)),
+            // a negated class excludes every case of what it lists; raw
+            // bytes have no case
+            Token::Class(negated, ranges, bytes) if self.case_insensitive => Ok(
+                create_class_with_bytes(negated, fold_ranges(&ranges), bytes),
+            ),
+            Token::Class(negated, ranges, bytes) => {
+                Ok(create_class_with_bytes(negated, ranges, bytes))
+            }
+            Token::LineStart => Ok(create_line_start()),
+            Token::LineEnd => Ok(create_line_end()),
+            Token::WordBoundary => Ok(create_word_boundary()),
+            Token::NotWordBoundary => Ok(create_not_word_boundary()),
+            Token::WordStart => Ok(create_word_start()),
+            Token::WordEnd => Ok(create_word_end()),
+            Token::LParen => self.group(None),
+            Token::FlagsLParen(case_insensitive) => {
+                let outer = self.case_insensitive;
+                self.case_insensitive = case_insensitive.unwrap_or(outer);
+                let expr = self.reg_expr()?;
+                self.consume_token(Token::RParen)?;
+                self.case_insensitive = outer;
+                Ok(expr)
+            }
+            Token::NamedLParen(name) => {
+                if self.names.contains(&name) {
+                    return Err(format!("Duplicate group name {}", name));
                 }
+                self.names.push(name.clone());
+                self.group(Some(name))
             }
-            _ => Err(format!("Unexpected atom encountered")),
+            // the tokenizer reports malformed escapes in-band
+            Token::Error(message) => Err(message),
+            _ => Err("Unexpected atom encountered".to_string()),
         }
     }
 }
@@ -238,7 +641,7 @@ impl<'tokens> Parser<'tokens> {
 mod private_api {
     use super::*;
 
-    mod parserLexemes {
+    mod parser_lexemes {
         use super::*;
 
         #[test]
@@ -250,7 +653,7 @@ mod private_api {
         fn union_bar_fail() {
             assert_eq!(
                 Parser::from("a|").reg_expr(),
-                Err(format!("Unexpected end of input"))
+                Err("Unexpected end of input".to_string())
             );
         }
         #[test]
@@ -268,6 +671,50 @@ mod private_api {
                 create_one_or_more(create_char('a'))
             );
         }
+        #[test]
+        fn optional() {
+            assert_eq!(
+                Parser::from("a?").closure().unwrap(),
+                create_optional(create_char('a'))
+            );
+        }
+
+        #[test]
+        fn repeat() {
+            assert_eq!(
+                Parser::from("a{2,}").closure().unwrap(),
+                create_repeat(create_char('a'), 2, None)
+            );
+            assert_eq!(
+                Parser::from("a{3}?").closure().unwrap(),
+                create_optional(create_repeat(create_char('a'), 3, Some(3)))
+            );
+        }
+
+        #[test]
+        fn repeat_bad_bounds() {
+            assert_eq!(
+                Parser::from("a{3,1}").closure(),
+                Err(String::from(
+                    "Invalid repetition {3,1}: minimum exceeds maximum"
+                ))
+            );
+            assert!(Parser::from("a{1001}").closure().is_err());
+        }
+
+        #[test]
+        fn repeat_nested_bounds() {
+            assert!(Parser::from("(a{100}){100}").closure().is_ok());
+            assert_eq!(
+                Parser::parse(Tokenizer::new("((a{1000}){1000}){1000}")),
+                Err(String::from(
+                    "Nested repetition expands past the limit of 100000 nodes at position 10"
+                ))
+            );
+            assert!(Parser::from("(a+){1000}b{1000}").closure().is_ok());
+            assert!(Parser::from("((a|b){500,}){500,}").closure().is_err());
+        }
+
         #[test]
         fn no_closure() {
             assert_eq!(Parser::from("a").closure().unwrap(), create_char('a'));
@@ -277,7 +724,7 @@ mod private_api {
         fn atom() {
             assert_eq!(
                 Parser::from("(ab)").atom().unwrap(),
-                create_catenation(create_char('a'), create_char('b'))
+                create_group(1, create_catenation(create_char('a'), create_char('b')))
             );
             assert_eq!(Parser::from(".").atom().unwrap(), create_any_char());
             assert_eq!(Parser::from("a").atom().unwrap(), create_char('a'));
@@ -297,7 +744,7 @@ mod private_api {
         fn rparen_fail() {
             assert_eq!(
                 Parser::from("(a").atom(),
-                Err(format!("Unexpected end of input"))
+                Err("Unexpected end of input".to_string())
             );
         }
     }
@@ -305,9 +752,25 @@ mod private_api {
 
 /* Parser's Helper Methods to improve ergonomics of parsing */
 impl<'tokens> Parser<'tokens> {
+    // the rest of a group once its left paren is taken; the group's index
+    // is claimed first so nested groups number after it, and flags set
+    // inside the group end with it
+    fn group(&mut self, name: Option<String>) -> Result<AST, String> {
+        self.groups += 1;
+        let index = self.groups;
+        let outer = self.case_insensitive;
+        let expr = self.reg_expr()?;
+        self.consume_token(Token::RParen)?;
+        self.case_insensitive = outer;
+        Ok(AST::Group(index, name, Box::new(expr)))
+    }
+
     fn from(input: &'tokens str) -> Parser<'tokens> {
         Parser {
-            tokens: Tokenizer::new(input).peekable(),
+            tokens: Tokenizer::new(input),
+            groups: 0,
+            names: vec![],
+            case_insensitive: false,
         }
     }
     fn take_next_token(&mut self) -> Result<Token, String> {
@@ -318,27 +781,17 @@ impl<'tokens> Parser<'tokens> {
         }
     }
 
-    //peek for kleenstar to determine if closure AST
-    fn peek_token(&mut self) -> Option<char> {
-        if let Some(Token::KleeneStar) = self.tokens.peek() {
-            Some('*')
-        } else if let Some(Token::KleenePlus) = self.tokens.peek() {
-            Some('+')
-        } else {
-            None
-        }
-    }
-
-    //consume the kleenstar token
-    fn take_token(&mut self) -> Result<char, String> {
-        if let Some(Token::KleeneStar) = self.tokens.peek() {
-            self.tokens.next();
-            Ok('*')
-        } else if let Some(Token::KleenePlus) = self.tokens.peek() {
-            self.tokens.next();
-            Ok('+')
-        } else {
-            Err(format!("KleeneStar not found."))
+    fn check_repeat(min: u32, max: Option<u32>) -> Result<(), String> {
+        match max {
+            Some(max) if max < min => Err(format!(
+                "Invalid repetition {{{},{}}}: minimum exceeds maximum",
+                min, max
+            )),
+            _ if max.unwrap_or(min) > REPEAT_LIMIT => Err(format!(
+                "Repetition count exceeds the limit of {}",
+                REPEAT_LIMIT
+            )),
+            _ => Ok(()),
         }
     }
 
@@ -1,7 +1,7 @@
 use std::iter::Peekable;
 use std::str::Chars;
 
-/**
+/*
  * thegrep - Tar Heel Extended Regular Expressions
  *
  * Author: Lily Lou, Taylor Montgomery
@@ -18,26 +18,150 @@ use std::str::Chars;
 #[derive(Debug, PartialEq)]
 pub enum Token {
     LParen,
+    // the opening paren of a named group, (?P<name> or (?<name>
+    NamedLParen(String),
+    // the opening paren of a group that captures nothing, (?: or (?i: or
+    // (?-i:, turning case-insensitivity on or off within it if Some
+    FlagsLParen(Option<bool>),
+    // (?i) or (?-i), turning case-insensitivity on or off until the end of
+    // the enclosing group
+    SetFlags(bool),
     RParen,
     UnionBar,
     KleeneStar,
     AnyChar,
     Char(char),
+    // \x80 to \xFF, a byte of the input that is not part of any char
+    Byte(u8),
     KleenePlus,
+    QuestionMark,
+    LineStart,
+    LineEnd,
+    // \b and \B, a boundary between a word char and a non-word one or not
+    WordBoundary,
+    NotWordBoundary,
+    // \< and \>, the start and end of a word
+    WordStart,
+    WordEnd,
+    // bounded repetition {m}, {m,}, {m,n} or {,n}: min, then max if any
+    Repeat(u32, Option<u32>),
+    // a bracket expression: negated?, then inclusive ranges of chars and
+    // of raw bytes
+    Class(bool, Vec<(char, char)>, Vec<(u8, u8)>),
+    Error(String),
 }
 
 /**
  * The internal state of a Tokenizer is maintained by a peekable character
- * iterator over a &str's Chars.
+ * iterator over a &str's Chars, which counts the chars it has consumed.
+ * A token can be peeked at, and the position of the last token lexed is
+ * kept so that errors can say where they happened.
  */
 pub struct Tokenizer<'str> {
-    chars: Peekable<Chars<'str>>,
+    chars: Cursor<'str>,
+    peeked: Option<Option<Token>>,
+    start: usize,
 }
 
 impl<'str> Tokenizer<'str> {
-    pub fn new(input: &'str str) -> Tokenizer {
+    pub fn new(input: &'str str) -> Tokenizer<'str> {
         Tokenizer {
-            chars: input.chars().peekable(),
+            chars: Cursor {
+                chars: input.chars().peekable(),
+                position: 0,
+            },
+            peeked: None,
+            start: 0,
+        }
+    }
+
+    /**
+     * The next token, without consuming it.
+     */
+    pub fn peek(&mut self) -> Option<&Token> {
+        if self.peeked.is_none() {
+            self.peeked = Some(self.lex());
+        }
+        self.peeked.as_ref().unwrap().as_ref()
+    }
+
+    /**
+     * The char offset at which the last token lexed, peeked or not,
+     * begins, or the length of the input once it has all been lexed.
+     */
+    pub fn position(&self) -> usize {
+        self.start
+    }
+
+    fn lex(&mut self) -> Option<Token> {
+        self.lex_endline();
+        self.start = self.chars.position;
+        let c = *self.chars.peek()?;
+        Some(match c {
+            '(' => self.lex_lparen(),
+            ')' => self.lex_rparen(),
+            '|' => self.lex_unionbar(),
+            '*' => self.lex_kleenestar(),
+            '.' => self.lex_anychar(),
+            '+' => self.lex_kleeneplus(),
+            '?' => self.lex_question_mark(),
+            '^' => self.lex_line_start(),
+            '$' => self.lex_line_end(),
+            '{' => self.lex_repeat(),
+            '[' => self.lex_class(),
+            _ => self.lex_char(),
+        })
+    }
+}
+
+/**
+ * A peekable iterator over chars that knows how many it has produced.
+ */
+#[derive(Clone)]
+struct Cursor<'str> {
+    chars: Peekable<Chars<'str>>,
+    position: usize,
+}
+
+impl<'str> Cursor<'str> {
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.position += 1;
+        Some(c)
+    }
+}
+
+/**
+ * A member of a bracket expression, which may be a raw byte.
+ */
+#[derive(Debug, Clone, Copy)]
+enum Member {
+    Char(char),
+    Byte(u8),
+}
+
+impl Member {
+    /**
+     * The byte value of an ASCII char or a raw byte.
+     */
+    fn byte(self) -> Option<u8> {
+        match self {
+            Member::Char(c) if c.is_ascii() => Some(c as u8),
+            Member::Char(_) => None,
+            Member::Byte(b) => Some(b),
+        }
+    }
+}
+
+impl std::fmt::Display for Member {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Member::Char(c) => write!(f, "{}", c),
+            Member::Byte(b) => write!(f, "\\x{:02x}", b),
         }
     }
 }
@@ -54,19 +178,9 @@ impl<'str> Iterator for Tokenizer<'str> {
      * complete Some(Token) in the Tokenizer's input string or None at all.
      */
     fn next(&mut self) -> Option<Token> {
-        self.lex_endline();
-        if let Some(c) = self.chars.peek() {
-            Some(match c {
-                '(' => self.lex_lparen(),
-                ')' => self.lex_rparen(),
-                '|' => self.lex_unionbar(),
-                '*' => self.lex_kleenestar(),
-                '.' => self.lex_anychar(),
-                '+' => self.lex_kleeneplus(),
-                _ => self.lex_char(),
-            })
-        } else {
-            None
+        match self.peeked.take() {
+            Some(peeked) => peeked,
+            None => self.lex(),
         }
     }
 }
@@ -84,6 +198,20 @@ mod iterator {
         assert_eq!(tokens.next(), None);
     }
 
+    #[test]
+    fn peek_and_position() {
+        let mut tokens = Tokenizer::new("a(?:bc)");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.position(), 0);
+        assert_eq!(tokens.peek(), Some(&Token::FlagsLParen(None)));
+        assert_eq!(tokens.position(), 1);
+        assert_eq!(tokens.next(), Some(Token::FlagsLParen(None)));
+        assert_eq!(tokens.next(), Some(Token::Char('b')));
+        assert_eq!(tokens.position(), 4);
+        assert_eq!(tokens.nth(2), None);
+        assert_eq!(tokens.position(), 7);
+    }
+
     #[test]
     fn lparen() {
         let mut tokens = Tokenizer::new("(");
@@ -132,6 +260,277 @@ mod iterator {
         assert_eq!(tokens.next(), Some(Token::KleenePlus));
         assert_eq!(tokens.next(), None);
     }
+    #[test]
+    fn escaped_metacharacters() {
+        let mut tokens = Tokenizer::new("\\(\\)\\|\\*\\.\\+\\\\");
+        assert_eq!(tokens.next(), Some(Token::Char('(')));
+        assert_eq!(tokens.next(), Some(Token::Char(')')));
+        assert_eq!(tokens.next(), Some(Token::Char('|')));
+        assert_eq!(tokens.next(), Some(Token::Char('*')));
+        assert_eq!(tokens.next(), Some(Token::Char('.')));
+        assert_eq!(tokens.next(), Some(Token::Char('+')));
+        assert_eq!(tokens.next(), Some(Token::Char('\\')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn raw_bytes() {
+        let mut tokens = Tokenizer::new("\\x7f\\xE9\\xff");
+        assert_eq!(tokens.next(), Some(Token::Char('\x7f')));
+        assert_eq!(tokens.next(), Some(Token::Byte(0xE9)));
+        assert_eq!(tokens.next(), Some(Token::Byte(0xFF)));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn class_bytes() {
+        let class = |pattern| Tokenizer::new(pattern).next().unwrap();
+        assert_eq!(
+            class("[\\xe9]"),
+            Token::Class(false, vec![], vec![(0xE9, 0xE9)])
+        );
+        assert_eq!(
+            class("[^a\\x80-\\xff]"),
+            Token::Class(true, vec![('a', 'a')], vec![(0x80, 0xFF)])
+        );
+        // a range of byte values may start at an ASCII char
+        assert_eq!(
+            class("[\\x00-\\xff]"),
+            Token::Class(false, vec![('\0', '\x7f')], vec![(0x80, 0xFF)])
+        );
+        assert_eq!(
+            class("[\\xe9-]"),
+            Token::Class(false, vec![('-', '-')], vec![(0xE9, 0xE9)])
+        );
+        assert_eq!(
+            class("[é-\\xff]"),
+            Token::Error(String::from("Invalid range é-\\xff in character class"))
+        );
+        assert_eq!(
+            class("[\\xff-\\x80]"),
+            Token::Error(String::from("Invalid range \\xff-\\x80 in character class"))
+        );
+    }
+
+    #[test]
+    fn escaped_special_chars() {
+        let mut tokens = Tokenizer::new("\\t\\n\\x41\\u{e9}\\u{1F600}");
+        assert_eq!(tokens.next(), Some(Token::Char('\t')));
+        assert_eq!(tokens.next(), Some(Token::Char('\n')));
+        assert_eq!(tokens.next(), Some(Token::Char('A')));
+        assert_eq!(tokens.next(), Some(Token::Char('é')));
+        assert_eq!(tokens.next(), Some(Token::Char('😀')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn bad_escapes() {
+        let mut tokens = Tokenizer::new("a\\");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Error(String::from("Trailing backslash")))
+        );
+        assert!(matches!(
+            Tokenizer::new("\\xg1").next(),
+            Some(Token::Error(_))
+        ));
+        assert!(matches!(
+            Tokenizer::new("\\u{}").next(),
+            Some(Token::Error(_))
+        ));
+        assert!(matches!(
+            Tokenizer::new("\\u{d800}").next(),
+            Some(Token::Error(_))
+        ));
+        assert!(matches!(
+            Tokenizer::new("\\q").next(),
+            Some(Token::Error(_))
+        ));
+    }
+
+    #[test]
+    fn class() {
+        let mut tokens = Tokenizer::new("[a-z0-9_]");
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Class(
+                false,
+                vec![('a', 'z'), ('0', '9'), ('_', '_')],
+                vec![]
+            ))
+        );
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn negated_class() {
+        let mut tokens = Tokenizer::new("[^.*]+");
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Class(true, vec![('.', '.'), ('*', '*')], vec![]))
+        );
+        assert_eq!(tokens.next(), Some(Token::KleenePlus));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn class_literal_bracket_and_dash() {
+        let mut tokens = Tokenizer::new("[]a-][^-]]");
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Class(
+                false,
+                vec![(']', ']'), ('a', 'a'), ('-', '-')],
+                vec![]
+            ))
+        );
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Class(true, vec![('-', '-')], vec![]))
+        );
+        assert_eq!(tokens.next(), Some(Token::Char(']')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn class_escapes() {
+        let mut tokens = Tokenizer::new("[\\]\\\\\\t-\\n]");
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Class(
+                false,
+                vec![(']', ']'), ('\\', '\\'), ('\t', '\n')],
+                vec![]
+            ))
+        );
+    }
+
+    #[test]
+    fn bad_classes() {
+        assert_eq!(
+            Tokenizer::new("[abc").next(),
+            Some(Token::Error(String::from("Unterminated character class")))
+        );
+        assert!(matches!(
+            Tokenizer::new("[z-a]").next(),
+            Some(Token::Error(_))
+        ));
+        assert!(matches!(
+            Tokenizer::new("[a-").next(),
+            Some(Token::Error(_))
+        ));
+    }
+
+    #[test]
+    fn question_mark() {
+        let mut tokens = Tokenizer::new("a?");
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::QuestionMark));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn anchors() {
+        let mut tokens = Tokenizer::new("^a\\$$");
+        assert_eq!(tokens.next(), Some(Token::LineStart));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Char('$')));
+        assert_eq!(tokens.next(), Some(Token::LineEnd));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn word_assertions() {
+        let mut tokens = Tokenizer::new("\\b\\B\\<\\>[\\<\\>]");
+        assert_eq!(tokens.next(), Some(Token::WordBoundary));
+        assert_eq!(tokens.next(), Some(Token::NotWordBoundary));
+        assert_eq!(tokens.next(), Some(Token::WordStart));
+        assert_eq!(tokens.next(), Some(Token::WordEnd));
+        assert_eq!(
+            tokens.next(),
+            Some(Token::Class(false, vec![('<', '<'), ('>', '>')], vec![]))
+        );
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn repeat() {
+        let mut tokens = Tokenizer::new("{4}{2,}{1,3}{,5}");
+        assert_eq!(tokens.next(), Some(Token::Repeat(4, Some(4))));
+        assert_eq!(tokens.next(), Some(Token::Repeat(2, None)));
+        assert_eq!(tokens.next(), Some(Token::Repeat(1, Some(3))));
+        assert_eq!(tokens.next(), Some(Token::Repeat(0, Some(5))));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn literal_braces() {
+        let mut tokens = Tokenizer::new("{a}{,}{1");
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), Some(Token::Char('a')));
+        assert_eq!(tokens.next(), Some(Token::Char('}')));
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), Some(Token::Char(',')));
+        assert_eq!(tokens.next(), Some(Token::Char('}')));
+        assert_eq!(tokens.next(), Some(Token::Char('{')));
+        assert_eq!(tokens.next(), Some(Token::Char('1')));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn repeat_overflow() {
+        assert!(matches!(
+            Tokenizer::new("{99999999999}").next(),
+            Some(Token::Error(_))
+        ));
+    }
+
+    #[test]
+    fn named_groups() {
+        let mut tokens = Tokenizer::new("(?P<year>)(?<_m1>)");
+        assert_eq!(
+            tokens.next(),
+            Some(Token::NamedLParen(String::from("year")))
+        );
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), Some(Token::NamedLParen(String::from("_m1"))));
+        assert_eq!(tokens.next(), Some(Token::RParen));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn group_flags() {
+        let mut tokens = Tokenizer::new("(?i)(?-i)(?:(?i:(?-i:");
+        assert_eq!(tokens.next(), Some(Token::SetFlags(true)));
+        assert_eq!(tokens.next(), Some(Token::SetFlags(false)));
+        assert_eq!(tokens.next(), Some(Token::FlagsLParen(None)));
+        assert_eq!(tokens.next(), Some(Token::FlagsLParen(Some(true))));
+        assert_eq!(tokens.next(), Some(Token::FlagsLParen(Some(false))));
+        assert_eq!(tokens.next(), None);
+    }
+
+    #[test]
+    fn bad_group_names() {
+        for pattern in &[
+            "(?P<>a)",
+            "(?<1a>a)",
+            "(?<a-b>a)",
+            "(?<abc",
+            "(?x)",
+            "(?)",
+            "(?-)",
+            "(?-:",
+            "(?i",
+        ] {
+            assert!(
+                matches!(Tokenizer::new(pattern).next(), Some(Token::Error(_))),
+                "{}",
+                pattern
+            );
+        }
+    }
+
     #[test]
     fn lex_phrase() {
         let mut tokens = Tokenizer::new("(2.\n*a)\n|b+");
@@ -164,11 +563,73 @@ impl<'str> Tokenizer<'str> {
     fn lex_lparen(&mut self) -> Token {
         let c = self.chars.next().unwrap();
         match c {
+            '(' if self.chars.peek() == Some(&'?') => self.lex_group_prefix(),
             '(' => Token::LParen,
             _ => panic!("Unexpected assignment helper"),
         }
     }
 
+    // (?P<name> and (?<name> open named groups; a name is a letter or
+    // underscore followed by letters, digits and underscores. Otherwise
+    // the prefix holds flags, of which only i is known.
+    fn lex_group_prefix(&mut self) -> Token {
+        self.chars.next();
+        match self.chars.peek() {
+            Some('P') | Some('<') => self.lex_group_name(),
+            _ => self.lex_flags(),
+        }
+    }
+
+    fn lex_group_name(&mut self) -> Token {
+        if self.chars.peek() == Some(&'P') {
+            self.chars.next();
+        }
+        if self.chars.next() != Some('<') {
+            return Token::Error(String::from("Unrecognized group syntax after (?"));
+        }
+        let mut name = String::new();
+        loop {
+            match self.chars.next() {
+                Some('>') => break,
+                Some(c) if c.is_alphanumeric() || c == '_' => name.push(c),
+                Some(c) => return Token::Error(format!("Invalid character {:?} in group name", c)),
+                None => return Token::Error(String::from("Unterminated group name")),
+            }
+        }
+        match name.chars().next() {
+            None => Token::Error(String::from("Empty group name")),
+            Some(first) if first.is_numeric() => {
+                Token::Error(format!("Group name {} must not start with a digit", name))
+            }
+            Some(_) => Token::NamedLParen(name),
+        }
+    }
+
+    // (?i) (?-i) (?: (?i: (?-i:
+    fn lex_flags(&mut self) -> Token {
+        let negated = self.chars.peek() == Some(&'-');
+        if negated {
+            self.chars.next();
+        }
+        let case_insensitive = match self.chars.peek() {
+            Some('i') => {
+                self.chars.next();
+                Some(!negated)
+            }
+            _ => None,
+        };
+        match (self.chars.next(), case_insensitive) {
+            (Some(':'), _) if case_insensitive.is_some() || !negated => {
+                Token::FlagsLParen(case_insensitive)
+            }
+            (Some(')'), Some(case_insensitive)) => Token::SetFlags(case_insensitive),
+            (Some(c), _) if c != ':' && c != ')' => {
+                Token::Error(format!("Unknown group flag {:?}", c))
+            }
+            _ => Token::Error(String::from("Unrecognized group syntax after (?")),
+        }
+    }
+
     fn lex_rparen(&mut self) -> Token {
         let c = self.chars.next().unwrap();
         match c {
@@ -203,7 +664,225 @@ impl<'str> Tokenizer<'str> {
 
     fn lex_char(&mut self) -> Token {
         let c = self.chars.next().unwrap();
-        Token::Char(c)
+        match c {
+            '\\' => self.lex_assertion().unwrap_or_else(|| self.lex_escape()),
+            _ => Token::Char(c),
+        }
+    }
+
+    // after a backslash, the word assertions \b, \B, \< and \>, which have
+    // no meaning inside a bracket expression and are not lexed there
+    fn lex_assertion(&mut self) -> Option<Token> {
+        let token = match self.chars.peek()? {
+            'b' => Token::WordBoundary,
+            'B' => Token::NotWordBoundary,
+            '<' => Token::WordStart,
+            '>' => Token::WordEnd,
+            _ => return None,
+        };
+        self.chars.next();
+        Some(token)
+    }
+
+    fn lex_question_mark(&mut self) -> Token {
+        let c = self.chars.next().unwrap();
+        match c {
+            '?' => Token::QuestionMark,
+            _ => panic!("Unexpected assignment helper"),
+        }
+    }
+
+    fn lex_line_start(&mut self) -> Token {
+        let c = self.chars.next().unwrap();
+        match c {
+            '^' => Token::LineStart,
+            _ => panic!("Unexpected assignment helper"),
+        }
+    }
+
+    fn lex_line_end(&mut self) -> Token {
+        let c = self.chars.next().unwrap();
+        match c {
+            '$' => Token::LineEnd,
+            _ => panic!("Unexpected assignment helper"),
+        }
+    }
+
+    // like egrep, a { that does not start a well-formed bound is literal
+    fn lex_repeat(&mut self) -> Token {
+        let mut probe = self.chars.clone();
+        probe.next();
+        let min = Tokenizer::lex_number(&mut probe);
+        let bounds = if probe.peek() == Some(&',') {
+            probe.next();
+            match (min, Tokenizer::lex_number(&mut probe)) {
+                (None, None) => None,
+                (min, max) => Some((min.unwrap_or(Ok(0)), max.transpose())),
+            }
+        } else {
+            min.map(|min| (min.clone(), min.map(Some)))
+        };
+        match (bounds, probe.next()) {
+            (Some(bounds), Some('}')) => {
+                self.chars = probe;
+                match bounds {
+                    (Ok(min), Ok(max)) => Token::Repeat(min, max),
+                    (Err(message), _) | (_, Err(message)) => Token::Error(message),
+                }
+            }
+            _ => self.lex_char(),
+        }
+    }
+
+    // None when there are no digits, Err when they don't fit in a u32
+    fn lex_number(chars: &mut Cursor) -> Option<Result<u32, String>> {
+        let mut digits = String::new();
+        while let Some(c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*c);
+            chars.next();
+        }
+        if digits.is_empty() {
+            None
+        } else {
+            Some(
+                digits
+                    .parse()
+                    .map_err(|_| format!("Repetition count {} is too large", digits)),
+            )
+        }
+    }
+
+    // a bracket expression runs to the first unescaped ] that is not the
+    // first member, so []] and [^]] include a literal ]. A - is literal
+    // when it can't form a range, as in [-a] or [a-].
+    fn lex_class(&mut self) -> Token {
+        self.chars.next();
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut ranges = Vec::new();
+        let mut bytes = Vec::new();
+        let mut first = true;
+        loop {
+            let lo = match self.lex_class_member(first) {
+                Ok(Some(member)) => member,
+                Ok(None) => return Token::Class(negated, ranges, bytes),
+                Err(message) => return Token::Error(message),
+            };
+            first = false;
+            let mut hi = lo;
+            if self.chars.peek() == Some(&'-') {
+                self.chars.next();
+                if self.chars.peek() == Some(&']') {
+                    // a lone member is always a valid range
+                    Tokenizer::add_range(&mut ranges, &mut bytes, lo, lo).unwrap();
+                    ranges.push(('-', '-'));
+                    continue;
+                }
+                hi = match self.lex_class_member(false) {
+                    Ok(Some(member)) => member,
+                    Ok(None) => unreachable!(),
+                    Err(message) => return Token::Error(message),
+                };
+            }
+            if let Err(message) = Tokenizer::add_range(&mut ranges, &mut bytes, lo, hi) {
+                return Token::Error(message);
+            }
+        }
+    }
+
+    // a range of chars, or of byte values when either end is a raw byte,
+    // so [\x00-\xff] is the ASCII chars and then every stray byte
+    fn add_range(
+        ranges: &mut Vec<(char, char)>,
+        bytes: &mut Vec<(u8, u8)>,
+        lo: Member,
+        hi: Member,
+    ) -> Result<(), String> {
+        match (lo, hi) {
+            (Member::Char(lo), Member::Char(hi)) if lo <= hi => ranges.push((lo, hi)),
+            _ => match (lo.byte(), hi.byte()) {
+                (Some(lo), Some(hi)) if lo <= hi => {
+                    if lo.is_ascii() {
+                        ranges.push((lo as char, hi.min(0x7F) as char));
+                    }
+                    if !hi.is_ascii() {
+                        bytes.push((lo.max(0x80), hi));
+                    }
+                }
+                _ => return Err(format!("Invalid range {}-{} in character class", lo, hi)),
+            },
+        }
+        Ok(())
+    }
+
+    // the next member of a bracket expression, or None at its closing ]
+    fn lex_class_member(&mut self, first: bool) -> Result<Option<Member>, String> {
+        match self.chars.next() {
+            Some(']') if !first => Ok(None),
+            Some('\\') => match self.lex_escape() {
+                Token::Char(c) => Ok(Some(Member::Char(c))),
+                Token::Byte(b) => Ok(Some(Member::Byte(b))),
+                Token::Error(message) => Err(message),
+                token => panic!("Unexpected escape token {:?}", token),
+            },
+            Some(c) => Ok(Some(Member::Char(c))),
+            None => Err(String::from("Unterminated character class")),
+        }
+    }
+
+    // called after a backslash has been consumed. Escaped metacharacters
+    // and punctuation stand for themselves, letters name special characters
+    fn lex_escape(&mut self) -> Token {
+        match self.chars.next() {
+            Some('t') => Token::Char('\t'),
+            Some('n') => Token::Char('\n'),
+            Some('r') => Token::Char('\r'),
+            Some('x') => match self.lex_hex_digits(2) {
+                Some(value) if value.is_ascii() => Token::Char(value),
+                Some(value) => Token::Byte(value as u8),
+                None => Token::Error(String::from("Expected two hex digits after \\x")),
+            },
+            Some('u') => self.lex_unicode_escape(),
+            Some(c) if c.is_alphanumeric() => {
+                Token::Error(format!("Unknown escape sequence \\{}", c))
+            }
+            Some(c) => Token::Char(c),
+            None => Token::Error(String::from("Trailing backslash")),
+        }
+    }
+
+    // \u{...} takes one to six hex digits naming a Unicode scalar value
+    fn lex_unicode_escape(&mut self) -> Token {
+        if self.chars.next() != Some('{') {
+            return Token::Error(String::from("Expected { after \\u"));
+        }
+        let mut value = 0;
+        let mut digits = 0;
+        while let Some(digit) = self.chars.peek().and_then(|c| c.to_digit(16)) {
+            self.chars.next();
+            value = value * 16 + digit;
+            digits += 1;
+            if digits > 6 {
+                return Token::Error(String::from("Too many hex digits in \\u{...}"));
+            }
+        }
+        if digits == 0 || self.chars.next() != Some('}') {
+            return Token::Error(String::from("Expected hex digits and } in \\u{...}"));
+        }
+        match std::char::from_u32(value) {
+            Some(c) => Token::Char(c),
+            None => Token::Error(format!("Invalid Unicode scalar value \\u{{{:x}}}", value)),
+        }
+    }
+
+    fn lex_hex_digits(&mut self, count: usize) -> Option<char> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value * 16 + self.chars.next()?.to_digit(16)?;
+        }
+        std::char::from_u32(value)
     }
 
     fn lex_kleeneplus(&mut self) -> Token {
@@ -228,6 +907,12 @@ mod private_api {
             assert_eq!(token.lex_lparen(), Token::LParen);
         }
 
+        #[test]
+        fn named_lparen() {
+            let mut token = Tokenizer::new("(?P<day>");
+            assert_eq!(token.lex_lparen(), Token::NamedLParen(String::from("day")));
+        }
+
         #[test]
         fn rparen() {
             let mut token = Tokenizer::new(")");
@@ -263,6 +948,40 @@ mod private_api {
             let mut token = Tokenizer::new("+");
             assert_eq!(token.lex_kleeneplus(), Token::KleenePlus);
         }
-    }
 
+        #[test]
+        fn question_mark() {
+            let mut token = Tokenizer::new("?");
+            assert_eq!(token.lex_question_mark(), Token::QuestionMark);
+        }
+
+        #[test]
+        fn anchors() {
+            assert_eq!(Tokenizer::new("^").lex_line_start(), Token::LineStart);
+            assert_eq!(Tokenizer::new("$").lex_line_end(), Token::LineEnd);
+        }
+
+        #[test]
+        fn repeat() {
+            let mut token = Tokenizer::new("{3,1}");
+            assert_eq!(token.lex_repeat(), Token::Repeat(3, Some(1)));
+        }
+
+        #[test]
+        fn class() {
+            let mut token = Tokenizer::new("[^a-c]");
+            assert_eq!(
+                token.lex_class(),
+                Token::Class(true, vec![('a', 'c')], vec![])
+            );
+        }
+
+        #[test]
+        fn escape() {
+            let mut token = Tokenizer::new("\\.");
+            assert_eq!(token.lex_char(), Token::Char('.'));
+            let mut token = Tokenizer::new("\\x2e");
+            assert_eq!(token.lex_char(), Token::Char('.'));
+        }
+    }
 }
@@ -0,0 +1,433 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use super::glob::Glob;
+use super::ignore::{self, Ignore};
+
+/*
+ * thegrep - Tar Heel Extended Regular Expressions - Directory Walking
+ *
+ * A recursive search walks each directory it is given depth first,
+ * visiting entries in name order so that output is the same every run.
+ * Like ripgrep it skips hidden entries, whose names start with a dot,
+ * and whatever the ignore files it finds along the way exclude, along
+ * with those above the root up to the top of its git repository.
+ */
+
+/**
+ * Which files a walk yields. Include and exclude globs are matched
+ * against file names and exclude_dir globs against directory names; a
+ * file is searched when it matches no exclude and, if any includes are
+ * given, at least one of them. Depth counts directories below the root,
+ * so a max_depth of 0 yields only a root that is a file. Hidden entries
+ * are walked only if hidden is set, and ignore files are read only if
+ * ignore_files is.
+ */
+#[derive(Debug, Default)]
+pub struct WalkOptions {
+    pub include: Vec<Glob>,
+    pub exclude: Vec<Glob>,
+    pub exclude_dir: Vec<Glob>,
+    pub max_depth: Option<usize>,
+    pub follow_links: bool,
+    pub hidden: bool,
+    pub ignore_files: bool,
+}
+
+/**
+ * The ignore files that apply within a directory: its own, in increasing
+ * precedence, then those of the directories above it. The global excludes
+ * file applies beneath the root, with the lowest precedence of all.
+ */
+struct Ignores {
+    parent: Option<Rc<Ignores>>,
+    files: Vec<Ignore>,
+}
+
+impl Ignores {
+    fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignores = Some(self);
+        while let Some(current) = ignores {
+            for file in current.files.iter().rev() {
+                if let Some(ignored) = file.matched(path, is_dir) {
+                    return ignored;
+                }
+            }
+            ignores = current.parent.as_deref();
+        }
+        false
+    }
+}
+
+/**
+ * An iterator over the files beneath a root, in depth-first name order.
+ * Entries that can't be read are yielded as errors and skipped. Symbolic
+ * links are followed only if follow_links is set, apart from the root,
+ * which is always followed; a directory reached twice through links is
+ * walked only the first time, so link cycles end.
+ */
+pub struct Walk<'o> {
+    options: &'o WalkOptions,
+    // paths still to visit, with their depth and the ignore files of the
+    // directory they are in, the next on top
+    stack: Vec<(PathBuf, usize, Rc<Ignores>)>,
+    visited: HashSet<PathBuf>,
+}
+
+impl<'o> Walk<'o> {
+    pub fn new(root: &Path, options: &'o WalkOptions) -> Walk<'o> {
+        let files = if options.ignore_files {
+            outer_ignores(root)
+        } else {
+            vec![]
+        };
+        let ignores = Rc::new(Ignores {
+            parent: None,
+            files,
+        });
+        Walk {
+            options,
+            stack: vec![(root.to_path_buf(), 0, ignores)],
+            visited: HashSet::new(),
+        }
+    }
+
+    /**
+     * Push the entries of a directory to be visited after it, with the
+     * ignore files that apply to them.
+     */
+    fn descend(&mut self, dir: &Path, depth: usize, ignores: Rc<Ignores>) -> Result<(), String> {
+        let error = |e: std::io::Error| format!("{}: {}", dir.display(), e);
+        if self.options.follow_links {
+            let real = fs::canonicalize(dir).map_err(error)?;
+            if !self.visited.insert(real) {
+                return Ok(());
+            }
+        }
+        let mut entries = fs::read_dir(dir)
+            .map_err(error)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .map_err(error)?;
+        entries.sort_unstable();
+        let ignores = self.read_ignores(dir, ignores);
+        self.stack.extend(
+            entries
+                .into_iter()
+                .rev()
+                .map(|path| (path, depth + 1, ignores.clone())),
+        );
+        Ok(())
+    }
+
+    /**
+     * Add the ignore files of a directory to those of its parents:
+     * .gitignore, then .ignore, which takes precedence.
+     */
+    fn read_ignores(&self, dir: &Path, parent: Rc<Ignores>) -> Rc<Ignores> {
+        if !self.options.ignore_files {
+            return parent;
+        }
+        let files: Vec<Ignore> = [".gitignore", ".ignore"]
+            .iter()
+            .filter_map(|name| Ignore::from_file(dir, &dir.join(name)))
+            .collect();
+        if files.is_empty() {
+            return parent;
+        }
+        Rc::new(Ignores {
+            parent: Some(parent),
+            files,
+        })
+    }
+
+    fn wanted_file(&self, path: &Path) -> bool {
+        let name = file_name(path);
+        let options = self.options;
+        (options.include.is_empty() || options.include.iter().any(|glob| glob.matches(&name)))
+            && !options.exclude.iter().any(|glob| glob.matches(&name))
+    }
+
+    fn wanted_dir(&self, path: &Path) -> bool {
+        let name = file_name(path);
+        !self
+            .options
+            .exclude_dir
+            .iter()
+            .any(|glob| glob.matches(&name))
+    }
+}
+
+impl<'o> Iterator for Walk<'o> {
+    type Item = Result<PathBuf, String>;
+
+    fn next(&mut self) -> Option<Result<PathBuf, String>> {
+        while let Some((path, depth, ignores)) = self.stack.pop() {
+            if depth > 0 && !self.options.hidden && file_name(&path).starts_with('.') {
+                continue;
+            }
+            let link = fs::symlink_metadata(&path);
+            let metadata = match link {
+                Ok(ref link) if depth > 0 && link.file_type().is_symlink() => {
+                    if !self.options.follow_links {
+                        continue;
+                    }
+                    fs::metadata(&path)
+                }
+                Ok(_) if depth > 0 => link,
+                _ => fs::metadata(&path),
+            };
+            let metadata = match metadata {
+                Ok(metadata) => metadata,
+                Err(e) => return Some(Err(format!("{}: {}", path.display(), e))),
+            };
+            if depth > 0 && ignores.ignored(&path, metadata.is_dir()) {
+                continue;
+            }
+            if metadata.is_dir() {
+                if self.options.max_depth.is_some_and(|max| depth >= max) {
+                    continue;
+                }
+                if depth > 0 && !self.wanted_dir(&path) {
+                    continue;
+                }
+                if let Err(msg) = self.descend(&path, depth, ignores) {
+                    return Some(Err(msg));
+                }
+            } else if depth == 0 || self.wanted_file(&path) {
+                return Some(Ok(path));
+            }
+        }
+        None
+    }
+}
+
+/**
+ * The ignore files that apply to a root from outside it, lowest
+ * precedence first: the global excludes file and, if the root is in a
+ * git repository, the repository's info/exclude and the .gitignore and
+ * .ignore files of each directory from the top of the repository down to
+ * the root's parent.
+ */
+fn outer_ignores(root: &Path) -> Vec<Ignore> {
+    let global = ignore::global_excludes();
+    let real = fs::canonicalize(root).ok();
+    let repo = real
+        .as_ref()
+        .and_then(|real| real.ancestors().find(|dir| dir.join(".git").exists()));
+    let (real, repo) = match (&real, repo) {
+        (Some(real), Some(repo)) => (real, repo),
+        _ => {
+            return global
+                .and_then(|path| Ignore::from_file(root, &path))
+                .into_iter()
+                .collect()
+        }
+    };
+    // each file's rules are relative to the directory it is in
+    let from = |dir: &Path, path: &Path| {
+        let prefix = real.strip_prefix(dir).unwrap();
+        Ignore::from_file(root, path).map(|ignore| ignore.beneath(prefix))
+    };
+    let mut files: Vec<Ignore> = global
+        .into_iter()
+        .chain(Some(repo.join(".git/info/exclude")))
+        .filter_map(|path| from(repo, &path))
+        .collect();
+    let parents: Vec<&Path> = real
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(repo))
+        .collect();
+    for dir in parents.into_iter().rev() {
+        for name in &[".gitignore", ".ignore"] {
+            files.extend(from(dir, &dir.join(name)));
+        }
+    }
+    files
+}
+
+/**
+ * The last component of a path, which globs are matched against.
+ */
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod public_api {
+    use super::*;
+
+    /**
+     * A fresh directory under the system's temporary directory.
+     */
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("thegrep-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for sub in &["a/b", "c", "target"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        for file in &[
+            "x.rs",
+            "y.txt",
+            "a/z.rs",
+            "a/b/w.rs",
+            "c/v.txt",
+            "target/t.rs",
+        ] {
+            fs::write(dir.join(file), "").unwrap();
+        }
+        dir
+    }
+
+    fn walk(dir: &Path, options: &WalkOptions) -> Vec<String> {
+        Walk::new(dir, options)
+            .map(|path| {
+                let path = path.unwrap();
+                let relative = path.strip_prefix(dir).unwrap();
+                relative.to_string_lossy().replace('\\', "/")
+            })
+            .collect()
+    }
+
+    fn globs(patterns: &[&str]) -> Vec<Glob> {
+        patterns.iter().map(|p| Glob::new(p).unwrap()).collect()
+    }
+
+    #[test]
+    fn walks_in_order() {
+        let dir = scratch("order");
+        assert_eq!(
+            walk(&dir, &WalkOptions::default()),
+            vec![
+                "a/b/w.rs",
+                "a/z.rs",
+                "c/v.txt",
+                "target/t.rs",
+                "x.rs",
+                "y.txt"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn filters() {
+        let dir = scratch("filters");
+        let options = WalkOptions {
+            include: globs(&["*.rs"]),
+            exclude: globs(&["z*"]),
+            exclude_dir: globs(&["targ?t"]),
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options), vec!["a/b/w.rs", "x.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_depth() {
+        let dir = scratch("depth");
+        let options = WalkOptions {
+            max_depth: Some(1),
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options), vec!["x.rs", "y.txt"]);
+        let options = WalkOptions {
+            max_depth: Some(0),
+            ..WalkOptions::default()
+        };
+        assert!(walk(&dir, &options).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hidden() {
+        let dir = scratch("hidden");
+        fs::create_dir(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git/config"), "").unwrap();
+        fs::write(dir.join("a/.env"), "").unwrap();
+        let options = WalkOptions {
+            exclude_dir: globs(&["a", "c", "target"]),
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options), vec!["x.rs", "y.txt"]);
+        let options = WalkOptions {
+            exclude_dir: globs(&["c", "target"]),
+            hidden: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(
+            walk(&dir, &options),
+            vec![
+                ".git/config",
+                "a/.env",
+                "a/b/w.rs",
+                "a/z.rs",
+                "x.rs",
+                "y.txt"
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignore_files() {
+        let dir = scratch("ignore");
+        fs::write(dir.join(".gitignore"), "target/\n*.txt\n").unwrap();
+        // a deeper file and .ignore take precedence
+        fs::write(dir.join("c/.gitignore"), "!v.txt\n").unwrap();
+        fs::write(dir.join("a/.ignore"), "/b\n").unwrap();
+        fs::write(dir.join("a/.gitignore"), "!b\n").unwrap();
+        let options = WalkOptions {
+            ignore_files: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options), vec!["a/z.rs", "c/v.txt", "x.rs"]);
+        let options = WalkOptions::default();
+        assert_eq!(walk(&dir, &options).len(), 6);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignore_files_above_root() {
+        let dir = scratch("ignore-above");
+        fs::create_dir_all(dir.join(".git/info")).unwrap();
+        fs::write(dir.join(".git/info/exclude"), "*.txt\n").unwrap();
+        fs::write(dir.join(".gitignore"), "/a/b\n").unwrap();
+        let options = WalkOptions {
+            ignore_files: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir.join("a"), &options), vec!["z.rs"]);
+        assert!(walk(&dir.join("c"), &options).is_empty());
+        // excludes are read only at the top of the repository
+        fs::create_dir_all(dir.join("a/.git/info")).unwrap();
+        fs::write(dir.join("a/.git/info/exclude"), "*.rs\n").unwrap();
+        assert_eq!(walk(&dir, &options), vec!["a/z.rs", "target/t.rs", "x.rs"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks() {
+        let dir = scratch("links");
+        std::os::unix::fs::symlink(dir.join("c"), dir.join("a/b/to_c")).unwrap();
+        // a cycle back to the root
+        std::os::unix::fs::symlink(&dir, dir.join("c/up")).unwrap();
+        let options = WalkOptions {
+            include: globs(&["*.txt"]),
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options), vec!["c/v.txt", "y.txt"]);
+        let options = WalkOptions {
+            include: globs(&["*.txt"]),
+            follow_links: true,
+            ..WalkOptions::default()
+        };
+        assert_eq!(walk(&dir, &options), vec!["a/b/to_c/v.txt", "y.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
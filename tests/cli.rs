@@ -0,0 +1,755 @@
+/*
+ * thegrep - Tar Heel egrep - Command Line Tests
+ *
+ * Each test runs the built binary over fixture files or standard input
+ * and checks its exact output and exit code.
+ */
+
+use assert_cmd::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const FRUIT: &str = "apple\nbanana\ncherry\n";
+
+fn thegrep() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_thegrep"))
+}
+
+/**
+ * A fresh directory of files for one test, which runs thegrep inside it.
+ */
+fn fixture(test: &str, files: &[(&str, &str)]) -> Command {
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("thegrep-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, text) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    let mut cmd = thegrep();
+    cmd.current_dir(dir);
+    cmd
+}
+
+mod invert_and_count {
+    use super::*;
+
+    #[test]
+    fn invert() {
+        thegrep()
+            .args(["-v", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("apple\ncherry\n");
+    }
+
+    #[test]
+    fn invert_selects_nothing() {
+        thegrep()
+            .args(["-v", "."])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(1)
+            .stdout("");
+    }
+
+    #[test]
+    fn count() {
+        thegrep()
+            .args(["-c", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("1\n");
+    }
+
+    #[test]
+    fn count_inverted() {
+        thegrep()
+            .args(["-vc", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("2\n");
+    }
+
+    #[test]
+    fn count_per_file() {
+        // a file with no selected lines still gets its count, and the
+        // exit code is 0 since one file had some
+        fixture("count", &[("f.txt", FRUIT), ("g.txt", "kiwi\n")])
+            .args(["-c", "an", "f.txt", "g.txt"])
+            .assert()
+            .code(0)
+            .stdout("f.txt:1\ng.txt:0\n");
+        fixture("count-none", &[("g.txt", "kiwi\n")])
+            .args(["-c", "an", "g.txt"])
+            .assert()
+            .code(1)
+            .stdout("0\n");
+    }
+}
+
+mod prefixes {
+    use super::*;
+
+    #[test]
+    fn line_number_and_byte_offset() {
+        thegrep()
+            .args(["-n", "-b", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("2:6:banana\n");
+    }
+
+    #[test]
+    fn column() {
+        // the column is that of the first match, counted in chars
+        thegrep()
+            .args(["-n", "--column", "na"])
+            .with_stdin()
+            .buffer("café banana\n")
+            .assert()
+            .code(0)
+            .stdout("1:8:café banana\n");
+    }
+
+    #[test]
+    fn with_and_without_filename() {
+        let files = [("f.txt", FRUIT), ("g.txt", "kiwi\n")];
+        fixture("with-filename", &files)
+            .args(["-H", "an", "f.txt"])
+            .assert()
+            .code(0)
+            .stdout("f.txt:banana\n");
+        fixture("no-filename", &files)
+            .args(["-h", "i", "f.txt", "g.txt"])
+            .assert()
+            .code(0)
+            .stdout("kiwi\n");
+        fixture("default-filename", &files)
+            .args(["i", "f.txt", "g.txt"])
+            .assert()
+            .code(0)
+            .stdout("g.txt:kiwi\n");
+    }
+
+    #[test]
+    fn vimgrep() {
+        // one line for each match, with file, line and column
+        fixture("vimgrep", &[("f.txt", FRUIT)])
+            .args(["--vimgrep", "an", "f.txt"])
+            .assert()
+            .code(0)
+            .stdout("f.txt:2:2:banana\nf.txt:2:4:banana\n");
+    }
+
+    #[test]
+    fn prefix_order() {
+        fixture("prefix-order", &[("f.txt", FRUIT)])
+            .args(["-H", "-n", "-b", "--column", "rr", "f.txt"])
+            .assert()
+            .code(0)
+            .stdout("f.txt:3:4:13:cherry\n");
+    }
+}
+
+mod context {
+    use super::*;
+
+    fn numbers() -> String {
+        (1..=12).map(|n| format!("{}\n", n)).collect()
+    }
+
+    #[test]
+    fn separates_groups() {
+        thegrep()
+            .args(["-n", "-C1", "^(3|10)$"])
+            .with_stdin()
+            .buffer(numbers())
+            .assert()
+            .code(0)
+            .stdout("2-2\n3:3\n4-4\n--\n9-9\n10:10\n11-11\n");
+    }
+
+    #[test]
+    fn merges_overlapping_windows() {
+        // the windows around 3 and 5 overlap and print as one group
+        thegrep()
+            .args(["-C1", "^(3|5|10)$"])
+            .with_stdin()
+            .buffer(numbers())
+            .assert()
+            .code(0)
+            .stdout("2\n3\n4\n5\n6\n--\n9\n10\n11\n");
+    }
+
+    #[test]
+    fn merges_adjacent_windows() {
+        // the after context of 4 ends just where the before context of
+        // 8 starts, so no separator comes between them
+        thegrep()
+            .args(["-A1", "-B2", "^(4|8)$"])
+            .with_stdin()
+            .buffer(numbers())
+            .assert()
+            .code(0)
+            .stdout("2\n3\n4\n5\n6\n7\n8\n9\n");
+    }
+
+    #[test]
+    fn selected_lines_in_context() {
+        // a selected line within another's context is printed as selected
+        thegrep()
+            .args(["-n", "-A2", "^(1|2)$"])
+            .with_stdin()
+            .buffer(numbers())
+            .assert()
+            .code(0)
+            .stdout("1:1\n2:2\n3-3\n4-4\n");
+    }
+
+    #[test]
+    fn huge_before_context() {
+        thegrep()
+            .args(["-B", "99999999999999", "^3$"])
+            .with_stdin()
+            .buffer(numbers())
+            .assert()
+            .code(0)
+            .stdout("1\n2\n3\n");
+    }
+}
+
+mod only_matching_and_color {
+    use super::*;
+
+    #[test]
+    fn only_matching() {
+        thegrep()
+            .args(["-o", "-b", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("7:an\n9:an\n");
+    }
+
+    #[test]
+    fn only_matching_skips_empty_matches() {
+        thegrep()
+            .args(["-o", "x*"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("");
+    }
+
+    #[test]
+    fn color_always() {
+        thegrep()
+            .args(["--color=always", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("b\x1b[01;31m\x1b[Kan\x1b[m\x1b[K\x1b[01;31m\x1b[Kan\x1b[m\x1b[Ka\n");
+    }
+
+    #[test]
+    fn color_prefixes() {
+        thegrep()
+            .args(["--color=always", "-n", "-o", "rr"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("\x1b[32m\x1b[K3\x1b[m\x1b[K\x1b[36m\x1b[K:\x1b[m\x1b[K\x1b[01;31m\x1b[Krr\x1b[m\x1b[K\n");
+    }
+
+    #[test]
+    fn color_never() {
+        thegrep()
+            .args(["--color=never", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("banana\n");
+    }
+}
+
+mod recursive {
+    use super::*;
+
+    const SKIPPED: [(&str, &str); 5] = [
+        ("kept.txt", "hit\n"),
+        (".hidden.txt", "hit\n"),
+        (".gitignore", "ignored.txt\n"),
+        ("ignored.txt", "hit\n"),
+        ("bin.dat", "hit\0\n"),
+    ];
+
+    #[test]
+    fn implicit_root() {
+        // files under the working directory are named without ./
+        let files = [("a.txt", "hit\n"), ("sub/b.txt", "hit\n")];
+        fixture("implicit-root", &files)
+            .args(["-r", "--sort", "path", "hit"])
+            .assert()
+            .code(0)
+            .stdout("a.txt:hit\nsub/b.txt:hit\n");
+    }
+
+    #[test]
+    fn skips_hidden_ignored_and_binary() {
+        fixture("skips", &SKIPPED)
+            .args(["-r", "--sort", "path", "hit"])
+            .assert()
+            .code(0)
+            .stdout("kept.txt:hit\n");
+    }
+
+    #[test]
+    fn hidden() {
+        fixture("hidden", &SKIPPED)
+            .args(["-r", "--sort", "path", "--hidden", "hit"])
+            .assert()
+            .code(0)
+            .stdout(".hidden.txt:hit\nkept.txt:hit\n");
+    }
+
+    #[test]
+    fn no_ignore() {
+        fixture("no-ignore", &SKIPPED)
+            .args(["-r", "--sort", "path", "--no-ignore", "hit"])
+            .assert()
+            .code(0)
+            .stdout("ignored.txt:hit\nkept.txt:hit\n");
+    }
+
+    #[test]
+    fn text() {
+        fixture("text", &SKIPPED)
+            .args(["-r", "--sort", "path", "-a", "hit"])
+            .assert()
+            .code(0)
+            .stdout("bin.dat:hit\0\nkept.txt:hit\n");
+    }
+
+    #[test]
+    fn ignore_files_above_root() {
+        let files = [
+            (".git/info/exclude", "*.log\n"),
+            (".gitignore", "/sub/gen\n"),
+            ("sub/gen/a.txt", "hit\n"),
+            ("sub/b.log", "hit\n"),
+            ("sub/c.txt", "hit\n"),
+        ];
+        fixture("above-root", &files)
+            .args(["-r", "hit", "sub"])
+            .assert()
+            .code(0)
+            .stdout("sub/c.txt:hit\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_directory() {
+        let files = [("data/f.txt", "hit\n"), ("tree/g.txt", "hit\n")];
+        let mut follow = fixture("symlinks", &files);
+        let dir = follow.get_current_dir().unwrap().to_path_buf();
+        std::os::unix::fs::symlink("../data", dir.join("tree/link")).unwrap();
+        follow
+            .args(["-R", "--sort", "path", "hit", "tree"])
+            .assert()
+            .code(0)
+            .stdout("tree/g.txt:hit\ntree/link/f.txt:hit\n");
+        thegrep()
+            .current_dir(dir)
+            .args(["-r", "--sort", "path", "hit", "tree"])
+            .assert()
+            .code(0)
+            .stdout("tree/g.txt:hit\n");
+    }
+}
+
+mod threads {
+    use super::*;
+
+    /**
+     * Files of very different lengths, so that threads finish them out of
+     * order, with every line matching.
+     */
+    fn files() -> Vec<(String, String)> {
+        (0..8)
+            .map(|file| {
+                let lines = if file % 2 == 0 { 5000 } else { 3 };
+                let text = (0..lines)
+                    .map(|line| format!("line {} of f{}\n", line, file))
+                    .collect();
+                (format!("f{}.txt", file), text)
+            })
+            .collect()
+    }
+
+    fn run(test: &str, args: &[&str]) -> String {
+        let files = files();
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(path, text)| (path.as_str(), text.as_str()))
+            .collect();
+        let output = fixture(test, &files).args(args).unwrap();
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn sorted_by_path() {
+        // the paths are given in reverse but printed in order
+        let expected: String = files()
+            .iter()
+            .flat_map(|(path, text)| text.lines().map(move |line| format!("{}:{}\n", path, line)))
+            .collect();
+        let mut args = vec!["-j", "4", "--sort", "path", "line"];
+        let paths: Vec<String> = files().into_iter().rev().map(|(path, _)| path).collect();
+        args.extend(paths.iter().map(String::as_str));
+        assert_eq!(run("sorted", &args), expected);
+    }
+
+    #[test]
+    fn files_never_interleave() {
+        let mut args = vec!["-j", "4", "line"];
+        let paths: Vec<String> = files().into_iter().map(|(path, _)| path).collect();
+        args.extend(paths.iter().map(String::as_str));
+        let output = run("interleave", &args);
+        // each file's lines come together and in order, whatever the
+        // order of the files
+        let mut seen: Vec<&str> = vec![];
+        for path in output.lines().map(|line| line.split(':').next().unwrap()) {
+            if seen.last() != Some(&path) {
+                assert!(!seen.contains(&path), "{} is split", path);
+                seen.push(path);
+            }
+        }
+        seen.sort_unstable();
+        assert_eq!(seen, paths);
+        assert_eq!(output.lines().count(), 4 * 5000 + 4 * 3);
+        for (path, text) in files() {
+            let printed: String = output
+                .lines()
+                .filter(|line| line.starts_with(&format!("{}:", path)))
+                .map(|line| format!("{}\n", &line[path.len() + 1..]))
+                .collect();
+            assert_eq!(printed, text);
+        }
+    }
+}
+
+mod errors {
+    use super::*;
+
+    const MISSING: &str = "thegrep: missing.txt: No such file or directory (os error 2)\n";
+
+    #[test]
+    fn bad_pattern() {
+        thegrep()
+            .args(["a(b|"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr("thegrep: Unexpected end of input at position 4\n");
+    }
+
+    #[test]
+    fn missing_file() {
+        // the warning does not stop the search of the other files
+        fixture("missing", &[("f.txt", FRUIT)])
+            .args(["an", "missing.txt", "f.txt"])
+            .assert()
+            .code(2)
+            .stdout("f.txt:banana\n")
+            .stderr(MISSING);
+    }
+
+    #[test]
+    fn no_messages() {
+        fixture("no-messages", &[("f.txt", FRUIT)])
+            .args(["-s", "an", "missing.txt", "f.txt"])
+            .assert()
+            .code(2)
+            .stdout("f.txt:banana\n")
+            .stderr("");
+    }
+
+    #[test]
+    fn quiet_match_after_error() {
+        fixture("quiet", &[("f.txt", FRUIT)])
+            .args(["-q", "an", "missing.txt", "f.txt"])
+            .assert()
+            .code(0)
+            .stdout("")
+            .stderr(MISSING);
+        fixture("quiet-none", &[("f.txt", FRUIT)])
+            .args(["-q", "kiwi", "missing.txt", "f.txt"])
+            .assert()
+            .code(2)
+            .stdout("");
+    }
+
+    #[test]
+    fn no_match() {
+        thegrep()
+            .args(["kiwi"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(1)
+            .stdout("")
+            .stderr("");
+    }
+}
+
+mod patterns {
+    use super::*;
+
+    #[test]
+    fn empty_pattern_matches_every_line() {
+        thegrep()
+            .args(["-e", ""])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout(FRUIT);
+        thegrep()
+            .args(["-c", ""])
+            .with_stdin()
+            .buffer("a\n\nb\n")
+            .assert()
+            .code(0)
+            .stdout("3\n");
+    }
+
+    #[test]
+    fn blank_line_in_pattern_file() {
+        fixture("blank-pattern", &[("patterns", "zzz\n\nyyy\n")])
+            .args(["-f", "patterns", "-n"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("1:apple\n2:banana\n3:cherry\n");
+    }
+
+    #[test]
+    fn empty_pattern_file_matches_nothing() {
+        fixture("empty-patterns", &[("patterns", "")])
+            .args(["-f", "patterns"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(1)
+            .stdout("");
+    }
+
+    #[test]
+    fn empty_pattern_whole_line() {
+        thegrep()
+            .args(["-n", "-x", ""])
+            .with_stdin()
+            .buffer("a\n\nb\n")
+            .assert()
+            .code(0)
+            .stdout("2:\n");
+    }
+
+    #[test]
+    fn show_patterns() {
+        thegrep()
+            .args(["--show-patterns", "-n", "-e", "an", "-e", "a$", "-e", "rr"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("2:1,2:banana\n3:3:cherry\n");
+        thegrep()
+            .args(["--show-patterns", "-e", "an", "-e", "a("])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(2)
+            .stdout("")
+            .stderr("thegrep: Unexpected end of input at position 2 in pattern 2\n");
+    }
+
+    #[test]
+    fn captures_shared_name() {
+        // both patterns name a group v, which is printed once with the
+        // value of the group that matched
+        thegrep()
+            .args(["--captures"])
+            .args(["-e", "(?P<k>[a-z]+)=(?P<v>[0-9]+)", "-e", "(?P<v>[0-9]+)%"])
+            .with_stdin()
+            .buffer("load 75%\nrate=5\n")
+            .assert()
+            .code(0)
+            .stdout("k=\tv=75\nk=rate\tv=5\n");
+    }
+}
+
+mod word_and_line {
+    use super::*;
+
+    const ENGINES: [&str; 3] = ["nfa", "dfa", "lazy"];
+
+    #[test]
+    fn word() {
+        for engine in &ENGINES {
+            thegrep()
+                .args(["--engine", engine, "-w", "id"])
+                .with_stdin()
+                .buffer("width\nid_x\n(id)\n")
+                .assert()
+                .code(0)
+                .stdout("(id)\n");
+        }
+    }
+
+    #[test]
+    fn line() {
+        for engine in &ENGINES {
+            thegrep()
+                .args(["--engine", engine, "-x", "ab"])
+                .with_stdin()
+                .buffer("abc\nab\nxab\n")
+                .assert()
+                .code(0)
+                .stdout("ab\n");
+        }
+    }
+}
+
+mod json {
+    use super::*;
+
+    #[test]
+    fn events_in_order() {
+        // the path is null for standard input
+        thegrep()
+            .args(["--json", "-C1", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout(concat!(
+                "{\"type\":\"begin\",\"path\":null}\n",
+                "{\"type\":\"context\",\"path\":null,\"line_number\":1,\"byte_offset\":0,",
+                "\"text\":\"apple\"}\n",
+                "{\"type\":\"match\",\"path\":null,\"line_number\":2,\"byte_offset\":6,",
+                "\"text\":\"banana\",\"submatches\":[{\"start\":1,\"end\":3,\"text\":\"an\"},",
+                "{\"start\":3,\"end\":5,\"text\":\"an\"}]}\n",
+                "{\"type\":\"context\",\"path\":null,\"line_number\":3,\"byte_offset\":13,",
+                "\"text\":\"cherry\"}\n",
+                "{\"type\":\"end\",\"path\":null,\"stats\":{\"lines\":3,\"matched_lines\":1,",
+                "\"matches\":2,\"bytes\":20}}\n",
+                "{\"type\":\"summary\",\"stats\":{\"files\":1,\"files_matched\":1,\"lines\":3,",
+                "\"matched_lines\":1,\"matches\":2,\"bytes\":20}}\n",
+            ));
+    }
+
+    #[test]
+    fn inverted_has_no_submatches() {
+        let output = thegrep()
+            .args(["--json", "-v", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .unwrap();
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let matches: Vec<&str> = stdout
+            .lines()
+            .filter(|event| event.starts_with("{\"type\":\"match\""))
+            .collect();
+        assert_eq!(
+            matches,
+            vec![
+                "{\"type\":\"match\",\"path\":null,\"line_number\":1,\"byte_offset\":0,\
+                 \"text\":\"apple\",\"submatches\":[]}",
+                "{\"type\":\"match\",\"path\":null,\"line_number\":3,\"byte_offset\":13,\
+                 \"text\":\"cherry\",\"submatches\":[]}",
+            ]
+        );
+    }
+
+    #[test]
+    fn quiet_prints_nothing() {
+        thegrep()
+            .args(["--json", "-q", "an"])
+            .with_stdin()
+            .buffer(FRUIT)
+            .assert()
+            .code(0)
+            .stdout("");
+    }
+}
+
+mod bytes {
+    use super::*;
+
+    const LATIN1: &[u8] = b"caf\xe9 ok\nplain\n\xff\xfe bad\n";
+
+    #[test]
+    fn stray_bytes_in_classes() {
+        for engine in &["nfa", "dfa", "lazy"] {
+            thegrep()
+                .args(["-n", "--engine", engine, "[\\x80-\\xff]"])
+                .with_stdin()
+                .buffer(LATIN1)
+                .assert()
+                .code(0)
+                .stdout(&b"1:caf\xe9 ok\n3:\xff\xfe bad\n"[..]);
+        }
+    }
+
+    #[test]
+    fn only_matching_bytes() {
+        thegrep()
+            .args(["-o", "[^\\x00-\\x7f]+"])
+            .with_stdin()
+            .buffer(LATIN1)
+            .assert()
+            .code(0)
+            .stdout(&b"\xe9\n\xff\xfe\n"[..]);
+    }
+
+    #[test]
+    fn binary_file_named() {
+        let files = [("some.bin", "ab\0c\nhit\n")];
+        fixture("binary", &files)
+            .args(["hit", "some.bin"])
+            .assert()
+            .code(0)
+            .stdout("Binary file some.bin matches\n");
+        fixture("binary-text", &files)
+            .args(["-a", "hit", "some.bin"])
+            .assert()
+            .code(0)
+            .stdout("hit\n");
+        fixture("binary-skipped", &files)
+            .args(["-I", "hit", "some.bin"])
+            .assert()
+            .code(1)
+            .stdout("");
+    }
+}